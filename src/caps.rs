@@ -0,0 +1,374 @@
+//! Linux capabilities: inspecting the sets of the current process, and
+//! keeping some of them when root privileges are dropped.
+use std::{fmt, fs, io, ops, str::FromStr};
+
+macro_rules! capabilities {
+    ($($(#[$doc:meta])* $cap:ident = $value:expr, $name:expr;)*) => {
+        /// A Linux capability, from `linux/capability.h`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[allow(non_camel_case_types)]
+        #[repr(u8)]
+        pub enum Capability {
+            $($(#[$doc])* $cap = $value,)*
+        }
+        impl Capability {
+            /// Every capability, in numeric order.
+            pub const ALL: &'static [Self] = &[$(Self::$cap,)*];
+
+            /// The name used by `capsh` and `/proc`, such as
+            /// `CAP_NET_BIND_SERVICE`.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$cap => $name,)*
+                }
+            }
+        }
+    };
+}
+
+capabilities!(
+    CHOWN = 0, "CAP_CHOWN";
+    /// Bypass file read, write and execute permission checks
+    DAC_OVERRIDE = 1, "CAP_DAC_OVERRIDE";
+    /// Bypass file read and directory search permission checks
+    DAC_READ_SEARCH = 2, "CAP_DAC_READ_SEARCH";
+    FOWNER = 3, "CAP_FOWNER";
+    FSETID = 4, "CAP_FSETID";
+    KILL = 5, "CAP_KILL";
+    SETGID = 6, "CAP_SETGID";
+    SETUID = 7, "CAP_SETUID";
+    SETPCAP = 8, "CAP_SETPCAP";
+    LINUX_IMMUTABLE = 9, "CAP_LINUX_IMMUTABLE";
+    /// Bind to ports below 1024
+    NET_BIND_SERVICE = 10, "CAP_NET_BIND_SERVICE";
+    NET_BROADCAST = 11, "CAP_NET_BROADCAST";
+    NET_ADMIN = 12, "CAP_NET_ADMIN";
+    /// Use raw and packet sockets, as ping does
+    NET_RAW = 13, "CAP_NET_RAW";
+    IPC_LOCK = 14, "CAP_IPC_LOCK";
+    IPC_OWNER = 15, "CAP_IPC_OWNER";
+    SYS_MODULE = 16, "CAP_SYS_MODULE";
+    SYS_RAWIO = 17, "CAP_SYS_RAWIO";
+    SYS_CHROOT = 18, "CAP_SYS_CHROOT";
+    SYS_PTRACE = 19, "CAP_SYS_PTRACE";
+    SYS_PACCT = 20, "CAP_SYS_PACCT";
+    SYS_ADMIN = 21, "CAP_SYS_ADMIN";
+    SYS_BOOT = 22, "CAP_SYS_BOOT";
+    SYS_NICE = 23, "CAP_SYS_NICE";
+    SYS_RESOURCE = 24, "CAP_SYS_RESOURCE";
+    SYS_TIME = 25, "CAP_SYS_TIME";
+    SYS_TTY_CONFIG = 26, "CAP_SYS_TTY_CONFIG";
+    MKNOD = 27, "CAP_MKNOD";
+    LEASE = 28, "CAP_LEASE";
+    AUDIT_WRITE = 29, "CAP_AUDIT_WRITE";
+    AUDIT_CONTROL = 30, "CAP_AUDIT_CONTROL";
+    SETFCAP = 31, "CAP_SETFCAP";
+    MAC_OVERRIDE = 32, "CAP_MAC_OVERRIDE";
+    MAC_ADMIN = 33, "CAP_MAC_ADMIN";
+    SYSLOG = 34, "CAP_SYSLOG";
+    WAKE_ALARM = 35, "CAP_WAKE_ALARM";
+    BLOCK_SUSPEND = 36, "CAP_BLOCK_SUSPEND";
+    AUDIT_READ = 37, "CAP_AUDIT_READ";
+    PERFMON = 38, "CAP_PERFMON";
+    BPF = 39, "CAP_BPF";
+    CHECKPOINT_RESTORE = 40, "CAP_CHECKPOINT_RESTORE";
+);
+impl Capability {
+    pub fn index(&self) -> u8 {
+        *self as u8
+    }
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+}
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+/// Parses names such as `CAP_NET_RAW`, `cap_net_raw` or `net_raw`.
+impl FromStr for Capability {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let upper = s.to_ascii_uppercase();
+        let name = upper.strip_prefix("CAP_").unwrap_or(&upper);
+        Self::ALL
+            .iter()
+            .find(|cap| &cap.name()[4..] == name)
+            .copied()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown capability {s:?}"),
+                )
+            })
+    }
+}
+
+/// A set of capabilities, stored as the bit mask the kernel uses.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CapSet(u64);
+impl CapSet {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+    pub fn contains(&self, cap: Capability) -> bool {
+        self.0 & 1 << cap.index() != 0
+    }
+    pub fn insert(&mut self, cap: Capability) -> &mut Self {
+        self.0 |= 1 << cap.index();
+        self
+    }
+    pub fn remove(&mut self, cap: Capability) -> &mut Self {
+        self.0 &= !(1 << cap.index());
+        self
+    }
+    pub fn iter(&self) -> impl Iterator<Item = Capability> + '_ {
+        Capability::ALL
+            .iter()
+            .copied()
+            .filter(|cap| self.contains(*cap))
+    }
+}
+impl From<Capability> for CapSet {
+    fn from(cap: Capability) -> Self {
+        *Self::empty().insert(cap)
+    }
+}
+impl FromIterator<Capability> for CapSet {
+    fn from_iter<T: IntoIterator<Item = Capability>>(iter: T) -> Self {
+        let mut set = Self::empty();
+        for cap in iter {
+            set.insert(cap);
+        }
+        set
+    }
+}
+impl ops::BitOr for CapSet {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+impl ops::BitOr<Capability> for CapSet {
+    type Output = Self;
+    fn bitor(self, rhs: Capability) -> Self {
+        self | Self::from(rhs)
+    }
+}
+/// Lists the capability names.
+impl fmt::Debug for CapSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.iter().map(|c| c.name()))
+            .finish()
+    }
+}
+
+/// The capability sets of a process, as listed in `/proc/<pid>/status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// What the process can do right now
+    pub effective: CapSet,
+    /// What the process may add to its effective set
+    pub permitted: CapSet,
+    /// What may be passed on to programs it executes
+    pub inheritable: CapSet,
+    /// The limit on capabilities gained on exec
+    pub bounding: CapSet,
+    /// What programs it executes get without file capabilities
+    pub ambient: CapSet,
+}
+impl Capabilities {
+    /// Reads the sets of the current thread.
+    pub fn current() -> io::Result<Self> {
+        Self::parse_status(&fs::read_to_string("/proc/thread-self/status")?)
+    }
+    /// Reads the sets of another process.
+    pub fn of_process(pid: u32) -> io::Result<Self> {
+        Self::parse_status(&fs::read_to_string(format!("/proc/{pid}/status"))?)
+    }
+    fn parse_status(status: &str) -> io::Result<Self> {
+        let field = |name: &str| -> io::Result<CapSet> {
+            let value = status
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("no {name} in status"))
+                })?;
+            u64::from_str_radix(value.trim(), 16)
+                .map(CapSet)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        };
+        Ok(Self {
+            effective: field("CapEff")?,
+            permitted: field("CapPrm")?,
+            inheritable: field("CapInh")?,
+            bounding: field("CapBnd")?,
+            // CapAmb appeared in Linux 4.3
+            ambient: field("CapAmb").unwrap_or_default(),
+        })
+    }
+}
+
+/// Returns true if the current thread has `cap` in its effective set.
+/// Example:
+///
+/// ```
+/// if !is_root_user() && !has_capability(Capability::NET_BIND_SERVICE) {
+///     eprintln!("cannot listen on port 80");
+/// }
+/// ```
+pub fn has_capability(cap: Capability) -> bool {
+    Capabilities::current().is_ok_and(|caps| caps.effective.contains(cap))
+}
+
+/// Sets `PR_SET_KEEPCAPS`, which keeps the permitted set when all uids
+/// change from root to another user. Like [`retain()`], it only applies
+/// to the calling thread.
+pub(crate) fn set_keep_caps(keep: bool) -> io::Result<()> {
+    prctl(libc::PR_SET_KEEPCAPS, keep as libc::c_ulong, 0)
+}
+
+/// Reduces the effective, permitted and inheritable sets to `caps`, and
+/// raises them in the ambient set so that programs executed later get
+/// them too. Capabilities belong to threads, and this only changes those
+/// of the calling thread.
+pub(crate) fn retain(caps: CapSet) -> io::Result<()> {
+    #[repr(C)]
+    struct Header {
+        version: u32,
+        pid: libc::c_int,
+    }
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Data {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+    let mut header = Header {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    // version 3 splits the 64 bit sets into two 32 bit halves
+    let half = |shift: u32| {
+        let bits = (caps.0 >> shift) as u32;
+        Data {
+            effective: bits,
+            permitted: bits,
+            inheritable: bits,
+        }
+    };
+    let data = [half(0), half(32)];
+    let r = unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) };
+    if r != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    for cap in caps.iter() {
+        prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+            cap.index() as libc::c_ulong,
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the number of threads in this process.
+pub(crate) fn thread_count() -> io::Result<usize> {
+    Ok(std::fs::read_dir("/proc/self/task")?.count())
+}
+
+fn prctl(option: libc::c_int, arg2: libc::c_ulong, arg3: libc::c_ulong) -> io::Result<()> {
+    if unsafe { libc::prctl(option, arg2, arg3, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = "Name:\tping\nUmask:\t0022\nCapInh:\t0000000000000000\n\
+        CapPrm:\t0000000000003000\nCapEff:\t0000000000002000\n\
+        CapBnd:\t000001ffffffffff\nCapAmb:\t0000000000000400\nNoNewPrivs:\t0\n";
+
+    #[test]
+    fn parse_status() {
+        let caps = Capabilities::parse_status(STATUS).unwrap();
+        assert_eq!(caps.effective, Capability::NET_RAW.into());
+        assert_eq!(
+            caps.permitted,
+            CapSet::from(Capability::NET_ADMIN) | Capability::NET_RAW
+        );
+        assert!(caps.inheritable.is_empty());
+        assert_eq!(caps.bounding.bits(), 0x1ff_ffff_ffff);
+        assert!(caps.bounding.iter().eq(Capability::ALL.iter().copied()));
+        assert_eq!(caps.ambient, Capability::NET_BIND_SERVICE.into());
+    }
+
+    #[test]
+    fn parse_status_errors() {
+        // kernels before 4.3 have no ambient set
+        let old = STATUS.replace("CapAmb:\t0000000000000400\n", "");
+        assert!(Capabilities::parse_status(&old).unwrap().ambient.is_empty());
+        let missing = STATUS.replace("CapEff", "CapXyz");
+        let e = Capabilities::parse_status(&missing).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let malformed = STATUS.replace("0000000000003000", "00000000000030g0");
+        let e = Capabilities::parse_status(&malformed).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn current() {
+        let caps = Capabilities::current().unwrap();
+        assert_eq!(caps.effective.bits() & !caps.permitted.bits(), 0);
+    }
+
+    #[test]
+    fn names() {
+        for name in ["CAP_NET_RAW", "cap_net_raw", "net_raw"] {
+            assert_eq!(name.parse::<Capability>().unwrap(), Capability::NET_RAW);
+        }
+        assert!("cap_nonsense".parse::<Capability>().is_err());
+        assert_eq!(
+            Capability::from_index(40),
+            Some(Capability::CHECKPOINT_RESTORE)
+        );
+        assert_eq!(Capability::from_index(41), None);
+        let set: CapSet = [Capability::KILL, Capability::CHOWN].into_iter().collect();
+        assert_eq!(format!("{set:?}"), r#"{"CAP_CHOWN", "CAP_KILL"}"#);
+    }
+
+    #[test]
+    fn retain_in_child() {
+        let keep = CapSet::from(Capability::NET_BIND_SERVICE) | Capability::NET_RAW;
+        crate::privileges::in_child(|| {
+            assert_eq!(thread_count()?, 1);
+            retain(keep)?;
+            let caps = Capabilities::current()?;
+            assert_eq!(caps.effective, keep);
+            assert_eq!(caps.permitted, keep);
+            assert_eq!(caps.inheritable, keep);
+            assert_eq!(caps.ambient, keep);
+            // a dropped capability cannot be raised again
+            assert!(retain(keep | Capability::SYS_ADMIN).is_err());
+            Ok(())
+        })
+        .unwrap();
+    }
+}
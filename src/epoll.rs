@@ -0,0 +1,258 @@
+//! Minimal safe wrapper around the Linux epoll API, plus a self-pipe for
+//! turning signals into readable fds.
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::atomic::{AtomicI32, Ordering},
+    time::Duration,
+};
+
+/// Converts a c return value (c_int) to an io Result, passing through
+/// non-negative values.
+fn cvt(c_return: libc::c_int) -> io::Result<libc::c_int> {
+    if c_return < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(c_return)
+    }
+}
+
+pub(crate) struct Epoll {
+    fd: OwnedFd,
+}
+impl Epoll {
+    pub fn new() -> io::Result<Self> {
+        let fd = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+    /// Watches `fd` for readability. `token` is returned by [`Self::wait()`].
+    /// With `oneshot`, the fd is disabled after the first event until it
+    /// is re-armed with another call to this function.
+    pub fn watch_readable(&self, fd: RawFd, token: u64, oneshot: bool) -> io::Result<()> {
+        let mut events = libc::EPOLLIN | libc::EPOLLRDHUP;
+        if oneshot {
+            events |= libc::EPOLLONESHOT;
+        }
+        let mut ev = libc::epoll_event {
+            events: events as u32,
+            u64: token,
+        };
+        let epfd = self.fd.as_raw_fd();
+        match cvt(unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fd, &mut ev) }) {
+            Err(e) if e.raw_os_error() == Some(libc::EEXIST) => {
+                cvt(unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_MOD, fd, &mut ev) })?;
+                Ok(())
+            }
+            r => r.map(|_| ()),
+        }
+    }
+    /// Stops watching `fd`.
+    pub fn unwatch(&self, fd: RawFd) -> io::Result<()> {
+        let mut ev = libc::epoll_event { events: 0, u64: 0 };
+        cvt(unsafe { libc::epoll_ctl(self.fd.as_raw_fd(), libc::EPOLL_CTL_DEL, fd, &mut ev) })?;
+        Ok(())
+    }
+    /// Waits for events, returning the tokens of the ready fds. A timeout
+    /// of `None` waits forever. Interrupted waits return no tokens.
+    pub fn wait(&self, tokens: &mut Vec<u64>, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = match timeout {
+            Some(d) => d.as_millis().min(libc::c_int::MAX as u128) as libc::c_int,
+            None => -1,
+        };
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; 32];
+        tokens.clear();
+        let n = match cvt(unsafe {
+            libc::epoll_wait(
+                self.fd.as_raw_fd(),
+                events.as_mut_ptr(),
+                events.len() as libc::c_int,
+                timeout,
+            )
+        }) {
+            Ok(n) => n as usize,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => 0,
+            Err(e) => return Err(e),
+        };
+        tokens.extend(events[..n].iter().map(|ev| ev.u64));
+        Ok(())
+    }
+}
+
+/// A nonblocking, monotonic clock `timerfd`.
+pub(crate) struct TimerFd {
+    fd: OwnedFd,
+}
+impl TimerFd {
+    /// Creates a timer that expires after `first`, then every `interval`.
+    /// An `interval` of zero makes a one-shot timer.
+    pub fn new(first: Duration, interval: Duration) -> io::Result<Self> {
+        let fd = cvt(unsafe {
+            libc::timerfd_create(
+                libc::CLOCK_MONOTONIC,
+                libc::TFD_NONBLOCK | libc::TFD_CLOEXEC,
+            )
+        })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // a zero it_value disarms the timer, so round up to 1ns
+        let first = first.max(Duration::from_nanos(1));
+        let spec = libc::itimerspec {
+            it_interval: timespec(interval),
+            it_value: timespec(first),
+        };
+        cvt(unsafe { libc::timerfd_settime(fd.as_raw_fd(), 0, &spec, std::ptr::null_mut()) })?;
+        Ok(Self { fd })
+    }
+    /// Returns the number of expirations since the last read, or `None` if
+    /// the timer has not expired.
+    pub fn read(&self) -> io::Result<Option<u64>> {
+        let mut count = 0u64;
+        let n = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                &mut count as *mut u64 as *mut libc::c_void,
+                8,
+            )
+        };
+        if n == 8 {
+            return Ok(Some(count));
+        }
+        let e = io::Error::last_os_error();
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => Ok(None),
+            _ => Err(e),
+        }
+    }
+}
+impl AsRawFd for TimerFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+fn timespec(d: Duration) -> libc::timespec {
+    libc::timespec {
+        tv_sec: d.as_secs() as libc::time_t,
+        tv_nsec: d.subsec_nanos() as libc::c_long,
+    }
+}
+
+/// Write ends of the signal pipes, indexed by signal number.
+static SIGNAL_FDS: [AtomicI32; 65] = [const { AtomicI32::new(-1) }; 65];
+
+extern "C" fn on_signal(signo: libc::c_int) {
+    // only async-signal-safe calls are allowed here
+    let errno = unsafe { *libc::__errno_location() };
+    if let Some(fd) = SIGNAL_FDS.get(signo as usize) {
+        let fd = fd.load(Ordering::Relaxed);
+        if fd >= 0 {
+            let b = signo as u8;
+            unsafe { libc::write(fd, &b as *const u8 as *const libc::c_void, 1) };
+        }
+    }
+    unsafe { *libc::__errno_location() = errno };
+}
+
+/// Delivers signals through a nonblocking pipe, so that they can be waited
+/// on together with other fds. Each byte read from the pipe is the number
+/// of a signal that arrived.
+///
+/// Only one `SignalPipe` may listen for a given signal at a time. The
+/// previous signal dispositions are restored on drop.
+pub(crate) struct SignalPipe {
+    read: OwnedFd,
+    write: OwnedFd,
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+impl SignalPipe {
+    pub fn new(signals: &[libc::c_int]) -> io::Result<Self> {
+        let mut fds = [0; 2];
+        cvt(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) })?;
+        let mut pipe = Self {
+            read: unsafe { OwnedFd::from_raw_fd(fds[0]) },
+            write: unsafe { OwnedFd::from_raw_fd(fds[1]) },
+            previous: Vec::new(),
+        };
+        for &signo in signals {
+            let slot = SIGNAL_FDS
+                .get(signo as usize)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+            if slot
+                .compare_exchange(-1, fds[1], Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("signal {signo} already has a listener"),
+                ));
+            }
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            unsafe { libc::sigemptyset(&mut action.sa_mask) };
+            let mut old: libc::sigaction = unsafe { std::mem::zeroed() };
+            if let Err(e) = cvt(unsafe { libc::sigaction(signo, &action, &mut old) }) {
+                slot.store(-1, Ordering::SeqCst);
+                return Err(e);
+            }
+            pipe.previous.push((signo, old));
+        }
+        Ok(pipe)
+    }
+    /// Reads pending signal numbers. Returns an empty Vec if none arrived.
+    pub fn drain(&self) -> io::Result<Vec<libc::c_int>> {
+        let mut signals = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            let n = unsafe {
+                libc::read(
+                    self.read.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                match e.kind() {
+                    io::ErrorKind::WouldBlock => return Ok(signals),
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(e),
+                }
+            }
+            if n == 0 {
+                return Ok(signals);
+            }
+            signals.extend(buf[..n as usize].iter().map(|&b| b as libc::c_int));
+        }
+    }
+}
+impl AsRawFd for SignalPipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.read.as_raw_fd()
+    }
+}
+impl Drop for SignalPipe {
+    fn drop(&mut self) {
+        for (signo, old) in self.previous.drain(..) {
+            unsafe { libc::sigaction(signo, &old, std::ptr::null_mut()) };
+            SIGNAL_FDS[signo as usize].store(-1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Returns true if `fd` has data to read, without blocking.
+pub(crate) fn is_readable(fd: RawFd) -> io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        match cvt(unsafe { libc::poll(&mut pfd, 1, 0) }) {
+            Ok(n) => return Ok(n > 0),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
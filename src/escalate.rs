@@ -0,0 +1,751 @@
+//! Re-running the current program as root through doas, sudo, run0,
+//! pkexec or su.
+use crate::{try_prompt_yn, InvokingUser, Reexec, Term};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::PermissionsExt,
+        process::CommandExt,
+    },
+    path::PathBuf,
+    process::Command,
+};
+
+/// Comes first among the arguments of the program re-run by
+/// [`Escalation::ensure_root()`], followed by the working directory or an
+/// empty argument, the number of preserved variables, and the variables
+/// as `NAME=value`. Every escalator passes the arguments on as they are,
+/// while doas, sudo, run0 and pkexec clean the environment.
+const STATE_ARG: &str = "--escalation-state";
+
+/// Programs that run a command as root. Each one tells the command who
+/// invoked it differently; where the program does not do it on its own,
+/// the information is passed along in extra environment variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escalator {
+    /// OpenBSD's doas. Sets `DOAS_USER`, and the uid is looked up from it.
+    Doas,
+    /// Sets `SUDO_USER` and `SUDO_UID`.
+    Sudo,
+    /// systemd's run0, which sets the same variables as sudo.
+    Run0,
+    /// polkit's pkexec. Sets `PKEXEC_UID`.
+    Pkexec,
+    /// `su root -c`. `SU_USER` and `SU_UID` are passed along.
+    Su,
+    /// Any program that runs its arguments as a command, such as a
+    /// wrapper script, or `env` to test without becoming root.
+    /// `ESCALATION_USER` and `ESCALATION_UID` are passed along. It is
+    /// never detected, and has to be chosen with
+    /// [`Escalation::escalator()`].
+    Custom(&'static str),
+}
+impl Escalator {
+    /// Every escalator, in the order [`Self::detect()`] tries them.
+    pub const ALL: [Self; 5] = [Self::Doas, Self::Sudo, Self::Run0, Self::Pkexec, Self::Su];
+
+    /// Name of the program, as looked up in `PATH`.
+    pub fn program(&self) -> &'static str {
+        match self {
+            Self::Doas => "doas",
+            Self::Sudo => "sudo",
+            Self::Run0 => "run0",
+            Self::Pkexec => "pkexec",
+            Self::Su => "su",
+            Self::Custom(program) => program,
+        }
+    }
+    /// Returns the first escalator found in `PATH`.
+    pub fn detect() -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.is_available())
+    }
+    /// Returns true if the program is an executable file in `PATH`.
+    pub fn is_available(&self) -> bool {
+        find_in_path(self.program()).is_some()
+    }
+    /// Returns the escalator that started this process, judging by the
+    /// environment variables it set.
+    pub fn from_env() -> Option<Self> {
+        [
+            ("DOAS_USER", Self::Doas),
+            ("PKEXEC_UID", Self::Pkexec),
+            ("SU_UID", Self::Su),
+            ("SUDO_UID", Self::Sudo),
+        ]
+        .into_iter()
+        .find(|(var, _)| env::var_os(var).is_some())
+        .map(|(_, e)| e)
+    }
+    /// Environment variables holding the name and uid of the invoking user.
+    /// doas only provides the name, and pkexec only the uid.
+    pub fn user_vars(&self) -> (Option<&'static str>, Option<&'static str>) {
+        match self {
+            Self::Doas => (Some("DOAS_USER"), None),
+            Self::Sudo | Self::Run0 => (Some("SUDO_USER"), Some("SUDO_UID")),
+            Self::Pkexec => (None, Some("PKEXEC_UID")),
+            Self::Su => (Some("SU_USER"), Some("SU_UID")),
+            Self::Custom(_) => (Some("ESCALATION_USER"), Some("ESCALATION_UID")),
+        }
+    }
+    /// Variables that must be passed along because the program does not
+    /// set them. Both programs keep the environment, so this costs
+    /// nothing.
+    fn passed_vars(&self) -> Vec<(&'static str, OsString)> {
+        let uid = nix::unistd::getuid().to_string().into();
+        match self {
+            Self::Su => vec![("SU_USER", current_user_name()), ("SU_UID", uid)],
+            Self::Custom(_) => vec![
+                ("ESCALATION_USER", current_user_name()),
+                ("ESCALATION_UID", uid),
+            ],
+            _ => Vec::new(),
+        }
+    }
+    /// Builds the command that runs `program` with `args` as root.
+    pub fn command(&self, program: impl AsRef<OsStr>, args: &[OsString]) -> Command {
+        self.command_with_env(program, args, &[])
+    }
+    /// Like [`Self::command()`], and sets the `vars` in the environment of
+    /// `program`, using what each escalator offers to let variables
+    /// through: `--preserve-env=` for sudo and `--setenv=` for run0, while
+    /// su and custom programs keep the environment anyway. sudoers may
+    /// still refuse variables that the rule does not allow with `SETENV`
+    /// or `env_keep`, and doas only passes on those that its rule in
+    /// doas.conf names with `setenv { NAME }` or allows with `keepenv`.
+    /// pkexec has no such option, so there the command becomes
+    /// `env NAME=value program args`, which polkit then sees as running
+    /// `env`, and which shows the values in the process list. Variables
+    /// with a `=` in their name, or a `,` for sudo, cannot be passed and
+    /// are skipped.
+    pub fn command_with_env(
+        &self,
+        program: impl AsRef<OsStr>,
+        args: &[OsString],
+        vars: &[(OsString, OsString)],
+    ) -> Command {
+        let vars = self
+            .passed_vars()
+            .into_iter()
+            .map(|(name, value)| (OsString::from(name), value))
+            .chain(vars.iter().cloned())
+            .filter(|(name, _)| {
+                let name = name.as_bytes();
+                !name.is_empty()
+                    && !name.contains(&b'=')
+                    && (*self != Self::Sudo || !name.contains(&b','))
+            })
+            .collect::<Vec<_>>();
+        let names = || vars.iter().map(|(name, _)| name.as_os_str());
+        let mut cmd = Command::new(self.program());
+        cmd.envs(vars.iter().map(|(name, value)| (name, value)));
+        let mut argv: Vec<OsString> = Vec::new();
+        match self {
+            Self::Sudo => {
+                if !vars.is_empty() {
+                    let mut list = OsString::from("--preserve-env=");
+                    list.push(names().collect::<Vec<_>>().join(OsStr::new(",")));
+                    cmd.arg(list);
+                }
+                cmd.arg("--");
+            }
+            Self::Run0 => {
+                for name in names() {
+                    let mut setenv = OsString::from("--setenv=");
+                    setenv.push(name);
+                    cmd.arg(setenv);
+                }
+            }
+            Self::Pkexec if !vars.is_empty() => {
+                argv.push("env".into());
+                for (name, value) in vars.iter() {
+                    let mut var = name.clone();
+                    var.push("=");
+                    var.push(value);
+                    argv.push(var);
+                }
+            }
+            _ => (),
+        }
+        argv.push(program.as_ref().to_os_string());
+        argv.extend(args.iter().cloned());
+        match self {
+            Self::Su => cmd.args(["root", "-c"]).arg(shell_words(&argv)),
+            _ => cmd.args(argv),
+        };
+        cmd
+    }
+}
+
+/// Reasons [`Escalation::ensure_root()`] failed. The `Display`
+/// implementation is meant to be shown to the user.
+#[derive(Debug)]
+pub enum EscalationError {
+    /// Not running as root, and none of the escalators is in `PATH`
+    NoEscalator,
+    /// The path of the current executable could not be found, or the
+    /// arguments could not be passed on
+    CurrentExe(io::Error),
+    /// The escalator could not be executed
+    ExecFailed {
+        escalator: Escalator,
+        error: io::Error,
+    },
+    /// The current working directory could not be found, so it could not
+    /// be preserved
+    CurrentDir(io::Error),
+    /// The arguments carrying the preserved variables and working
+    /// directory across the escalator could not be parsed
+    StateMalformed,
+    /// Running as root through an escalator that did not set a variable
+    EnvMissing(&'static str),
+    /// A variable set by the escalator could not be parsed
+    EnvMalformed { var: &'static str, value: String },
+    /// The uid passed by the escalator does not belong to any user
+    UnknownUid(u32),
+    /// The user name passed by the escalator does not match the uid
+    UserMismatch { name: String, uid: u32 },
+    /// The user or group database could not be read
+    UserLookup(io::Error),
+    /// Running as root, but not through an escalator, such as from a root
+    /// shell, so the invoking user is not known
+    RootWithoutEscalation,
+    /// The preserved working directory could not be entered
+    Chdir { dir: PathBuf, error: io::Error },
+    /// Confirmation or a terminal was required, but the process has no
+    /// controlling terminal
+    NoTerminal,
+    /// The user answered no when asked to confirm
+    Declined,
+    /// Asking for confirmation on the terminal failed
+    Terminal(io::Error),
+}
+impl fmt::Display for EscalationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoEscalator => write!(
+                f,
+                "this program must run as root, but none of doas, sudo, run0, pkexec or su was found in PATH"
+            ),
+            Self::CurrentExe(e) => write!(f, "cannot re-run this program: {e}"),
+            Self::ExecFailed { escalator, error } => {
+                write!(f, "cannot run {}: {error}", escalator.program())
+            }
+            Self::CurrentDir(e) => write!(f, "cannot find the current directory: {e}"),
+            Self::StateMalformed => write!(
+                f,
+                "the arguments passed on through the escalator are malformed"
+            ),
+            Self::EnvMissing(var) => write!(f, "the {var} environment variable is not set"),
+            Self::EnvMalformed { var, value } => {
+                write!(f, "the {var} environment variable has an invalid value {value:?}")
+            }
+            Self::UnknownUid(uid) => write!(f, "there is no user with uid {uid}"),
+            Self::UserMismatch { name, uid } => {
+                write!(f, "the invoking user {name} does not have uid {uid}")
+            }
+            Self::UserLookup(e) => write!(f, "cannot look up the invoking user: {e}"),
+            Self::RootWithoutEscalation => write!(
+                f,
+                "this program must be started through doas, sudo, run0, pkexec or su, not as root directly"
+            ),
+            Self::Chdir { dir, error } => {
+                write!(f, "cannot change directory to {}: {error}", dir.display())
+            }
+            Self::NoTerminal => write!(
+                f,
+                "this program must run as root, and cannot ask for permission without a terminal; run it as root instead"
+            ),
+            Self::Declined => write!(f, "not running as root, since permission was declined"),
+            Self::Terminal(e) => write!(f, "cannot ask for permission on the terminal: {e}"),
+        }
+    }
+}
+impl std::error::Error for EscalationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::CurrentExe(e)
+            | Self::CurrentDir(e)
+            | Self::ExecFailed { error: e, .. }
+            | Self::Chdir { error: e, .. }
+            | Self::UserLookup(e)
+            | Self::Terminal(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<EscalationError> for io::Error {
+    fn from(e: EscalationError) -> Self {
+        let kind = match &e {
+            EscalationError::NoEscalator | EscalationError::UnknownUid(_) => {
+                io::ErrorKind::NotFound
+            }
+            EscalationError::CurrentExe(e)
+            | EscalationError::CurrentDir(e)
+            | EscalationError::ExecFailed { error: e, .. }
+            | EscalationError::Chdir { error: e, .. }
+            | EscalationError::UserLookup(e)
+            | EscalationError::Terminal(e) => e.kind(),
+            EscalationError::StateMalformed
+            | EscalationError::EnvMissing(_)
+            | EscalationError::EnvMalformed { .. }
+            | EscalationError::UserMismatch { .. } => io::ErrorKind::InvalidData,
+            EscalationError::RootWithoutEscalation | EscalationError::Declined => {
+                io::ErrorKind::PermissionDenied
+            }
+            EscalationError::NoTerminal => io::ErrorKind::NotConnected,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Runs the current program as root if it is not already, with the same
+/// arguments. Which escalator is used can be chosen with
+/// [`Self::escalator()`]; otherwise the first one found in `PATH` is used.
+///
+/// Escalators replace the environment with a minimal one, and most start
+/// the program in `/` or root's home directory. Variables that the program
+/// needs can be listed with [`Self::preserve_env()`] or
+/// [`Self::preserve_env_prefix()`], and the working directory kept with
+/// [`Self::preserve_cwd()`]. They are passed as extra arguments, which
+/// every escalator passes on whatever its configuration, and the program
+/// running as root takes them out again by running itself once more
+/// without them, before `ensure_root()` returns. Arguments can be seen by
+/// other users in the process list, so secrets should not be passed this
+/// way. Example:
+///
+/// ```
+/// let user = Escalation::new()
+///     .escalator(Escalator::Sudo)
+///     .preserve_env(["HOME", "TERM", "LANG", "MYTOOL_CONFIG"])
+///     .preserve_env_prefix("XDG_")
+///     .preserve_cwd()
+///     .ensure_root()?;
+/// println!("running as root on behalf of {user} ({})", user.uid());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Escalation {
+    escalator: Option<Escalator>,
+    env: Vec<OsString>,
+    env_prefixes: Vec<OsString>,
+    cwd: bool,
+    reason: Option<String>,
+    require_terminal: bool,
+}
+impl Escalation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn escalator(&mut self, escalator: Escalator) -> &mut Self {
+        self.escalator = Some(escalator);
+        self
+    }
+    /// Passes the named environment variables, if they are set, to the
+    /// program running as root. Only the variables named here are taken
+    /// from the arguments on the other side, so the same `Escalation` has
+    /// to be used there.
+    pub fn preserve_env<S: AsRef<OsStr>>(
+        &mut self,
+        names: impl IntoIterator<Item = S>,
+    ) -> &mut Self {
+        self.env
+            .extend(names.into_iter().map(|n| n.as_ref().to_os_string()));
+        self
+    }
+    /// Passes every environment variable whose name starts with `prefix`.
+    pub fn preserve_env_prefix(&mut self, prefix: impl AsRef<OsStr>) -> &mut Self {
+        self.env_prefixes.push(prefix.as_ref().to_os_string());
+        self
+    }
+    /// Changes to the current working directory on the other side. Most
+    /// escalators start the program in `/` or root's home directory.
+    pub fn preserve_cwd(&mut self) -> &mut Self {
+        self.cwd = true;
+        self
+    }
+    /// Before running the escalator, shows `reason` on the controlling
+    /// terminal and asks whether to continue, failing with
+    /// [`EscalationError::Declined`] unless the answer is yes. Implies
+    /// [`Self::require_terminal()`]. Example:
+    ///
+    /// ```
+    /// Escalation::new()
+    ///     .confirm("Installing the service needs to write to /etc/systemd/system.")
+    ///     .ensure_root()?;
+    /// ```
+    pub fn confirm(&mut self, reason: impl Into<String>) -> &mut Self {
+        self.reason = Some(reason.into());
+        self.require_terminal = true;
+        self
+    }
+    /// Fails with [`EscalationError::NoTerminal`] instead of running the
+    /// escalator when there is no controlling terminal, as under cron or
+    /// a service manager, where a password prompt would hang or go
+    /// unseen.
+    pub fn require_terminal(&mut self) -> &mut Self {
+        self.require_terminal = true;
+        self
+    }
+    /// Checks for a terminal and asks for confirmation, as configured.
+    fn confirm_on_tty(&self, escalator: Escalator) -> Result<(), EscalationError> {
+        if !self.require_terminal {
+            return Ok(());
+        }
+        // opening /dev/tty fails when there is no controlling terminal
+        let tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(|_| EscalationError::NoTerminal)?;
+        let Some(reason) = &self.reason else {
+            return Ok(());
+        };
+        let output = tty.try_clone().map_err(|_| EscalationError::NoTerminal)?;
+        let mut term = Term::new(tty, output).map_err(|_| EscalationError::NoTerminal)?;
+        let confirmed = writeln!(term, "{reason}").and_then(|_| {
+            try_prompt_yn(
+                &mut term,
+                Some(false),
+                format_args!("Run as root with {}", escalator.program()),
+            )
+        });
+        _ = writeln!(term);
+        match confirmed {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(EscalationError::Declined),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Err(EscalationError::Declined),
+            Err(e) => Err(EscalationError::Terminal(e)),
+        }
+    }
+    fn preserves(&self, name: &OsStr) -> bool {
+        self.env.iter().any(|n| n == name)
+            || self
+                .env_prefixes
+                .iter()
+                .any(|p| name.as_bytes().starts_with(p.as_bytes()))
+    }
+    /// The arguments that carry the preserved variables and working
+    /// directory across the escalator. See [`STATE_ARG`].
+    fn state_args(&self) -> Result<Vec<OsString>, EscalationError> {
+        let cwd = match self.cwd {
+            true => env::current_dir()
+                .map_err(EscalationError::CurrentDir)?
+                .into_os_string(),
+            false => OsString::new(),
+        };
+        let vars: Vec<OsString> = env::vars_os()
+            .filter(|(name, _)| self.preserves(name))
+            .map(|(mut var, value)| {
+                var.push("=");
+                var.push(value);
+                var
+            })
+            .collect();
+        let mut args = vec![STATE_ARG.into(), cwd, vars.len().to_string().into()];
+        args.extend(vars);
+        Ok(args)
+    }
+    /// If the effective user is root, returns the user who ran the
+    /// escalator. Otherwise, replaces the current process with
+    /// the escalator running the current executable, and only returns if
+    /// that fails. Example of reporting errors:
+    ///
+    /// ```
+    /// let user = match ensure_root() {
+    ///     Ok(user) => user,
+    ///     Err(e) => {
+    ///         eprintln!("{e}");
+    ///         std::process::exit(1);
+    ///     }
+    /// };
+    /// ```
+    pub fn ensure_root(&self) -> Result<InvokingUser, EscalationError> {
+        if nix::unistd::geteuid().is_root() {
+            if let Some(state) = PassedState::from_args(env::args_os())? {
+                state.apply(|name| self.preserves(name), self.cwd)?;
+                // run again without the extra arguments, so that the
+                // program sees only its own
+                let error = Reexec::new().args(state.args).exec();
+                return Err(EscalationError::CurrentExe(error));
+            }
+            return self.invoking_user();
+        }
+        let escalator = self.chosen_escalator()?;
+        let error = self.reexec_command(escalator, None)?.exec();
+        Err(EscalationError::ExecFailed { escalator, error })
+    }
+    /// The escalator that was chosen, or else the first one found.
+    pub(crate) fn chosen_escalator(&self) -> Result<Escalator, EscalationError> {
+        self.escalator
+            .or_else(Escalator::detect)
+            .ok_or(EscalationError::NoEscalator)
+    }
+    /// Asks for confirmation if needed, and builds the command that runs
+    /// this program through `escalator`, with `args` instead of the
+    /// current arguments if given, after the preserved state.
+    pub(crate) fn reexec_command(
+        &self,
+        escalator: Escalator,
+        args: Option<Vec<OsString>>,
+    ) -> Result<Command, EscalationError> {
+        self.confirm_on_tty(escalator)?;
+        let args = args.unwrap_or_else(|| env::args_os().skip(1).collect());
+        let (exe, args, _) = Reexec::new()
+            .args(self.state_args()?.into_iter().chain(args))
+            .program(true)
+            .map_err(EscalationError::CurrentExe)?;
+        Ok(escalator.command(exe, &args))
+    }
+    fn invoking_user(&self) -> Result<InvokingUser, EscalationError> {
+        let escalator = self
+            .escalator
+            .or_else(Escalator::from_env)
+            .ok_or(EscalationError::RootWithoutEscalation)?;
+        let (user_var, uid_var) = escalator.user_vars();
+        let Some(uid_var) = uid_var else {
+            // every escalator sets at least one of the two
+            let name = env_var(user_var.expect("Should have a user variable"))?;
+            return InvokingUser::from_name(&name).map_err(EscalationError::UserLookup);
+        };
+        let uid = env_var(uid_var)?;
+        let uid = uid
+            .parse::<u32>()
+            .map_err(|_| EscalationError::EnvMalformed {
+                var: uid_var,
+                value: uid,
+            })?;
+        let Some(user_var) = user_var else {
+            return InvokingUser::from_uid(uid).map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => EscalationError::UnknownUid(uid),
+                _ => EscalationError::UserLookup(e),
+            });
+        };
+        let name = env_var(user_var)?;
+        InvokingUser::with_name(&name, uid).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => EscalationError::UnknownUid(uid),
+            io::ErrorKind::InvalidData => EscalationError::UserMismatch { name, uid },
+            _ => EscalationError::UserLookup(e),
+        })
+    }
+}
+
+/// The variables and working directory passed across the escalator in
+/// the arguments, and the arguments that follow them.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct PassedState {
+    cwd: Option<PathBuf>,
+    vars: Vec<(OsString, OsString)>,
+    args: Vec<OsString>,
+}
+impl PassedState {
+    /// Takes the state from `argv` if the arguments start with
+    /// [`STATE_ARG`].
+    pub(crate) fn from_args(
+        argv: impl IntoIterator<Item = OsString>,
+    ) -> Result<Option<Self>, EscalationError> {
+        let mut args = argv.into_iter().skip(1).peekable();
+        if args.next_if(|a| a == STATE_ARG).is_none() {
+            return Ok(None);
+        }
+        let cwd = args.next().ok_or(EscalationError::StateMalformed)?;
+        let count = args
+            .next()
+            .and_then(|n| n.to_str()?.parse::<usize>().ok())
+            .ok_or(EscalationError::StateMalformed)?;
+        let mut vars = Vec::new();
+        for _ in 0..count {
+            let var = args.next().ok_or(EscalationError::StateMalformed)?;
+            let bytes = var.as_bytes();
+            let eq = bytes
+                .iter()
+                .position(|b| *b == b'=')
+                .ok_or(EscalationError::StateMalformed)?;
+            vars.push((
+                OsStr::from_bytes(&bytes[..eq]).to_os_string(),
+                OsStr::from_bytes(&bytes[eq + 1..]).to_os_string(),
+            ));
+        }
+        Ok(Some(Self {
+            cwd: (!cwd.is_empty()).then(|| cwd.into()),
+            vars,
+            args: args.collect(),
+        }))
+    }
+    /// Sets the variables whose names `keep` accepts, and changes to the
+    /// working directory if `cwd` is set. Fails if it was not passed.
+    pub(crate) fn apply(
+        &self,
+        keep: impl Fn(&OsStr) -> bool,
+        cwd: bool,
+    ) -> Result<(), EscalationError> {
+        for (name, value) in self.vars.iter() {
+            if keep(name) {
+                env::set_var(name, value);
+            }
+        }
+        if cwd {
+            let dir = self.cwd.clone().ok_or(EscalationError::StateMalformed)?;
+            env::set_current_dir(&dir).map_err(|error| EscalationError::Chdir { dir, error })?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the current program as root with the first escalator found in
+/// `PATH`. See [`Escalation::ensure_root()`].
+pub fn ensure_root() -> Result<InvokingUser, EscalationError> {
+    Escalation::new().ensure_root()
+}
+
+fn env_var(var: &'static str) -> Result<String, EscalationError> {
+    match env::var(var) {
+        Ok(value) => Ok(value),
+        Err(env::VarError::NotPresent) => Err(EscalationError::EnvMissing(var)),
+        Err(env::VarError::NotUnicode(value)) => Err(EscalationError::EnvMalformed {
+            var,
+            value: value.to_string_lossy().into_owned(),
+        }),
+    }
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|p| {
+            p.metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+fn current_user_name() -> OsString {
+    nix::unistd::User::from_uid(nix::unistd::getuid())
+        .ok()
+        .flatten()
+        .map(|u| u.name.into())
+        .unwrap_or_default()
+}
+
+/// Joins words into a command line for `sh -c`, single quoting each one.
+fn shell_words(words: &[OsString]) -> OsString {
+    let mut line = Vec::new();
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            line.push(b' ');
+        }
+        line.push(b'\'');
+        for &b in word.as_bytes() {
+            match b {
+                b'\'' => line.extend_from_slice(b"'\\''"),
+                b => line.push(b),
+            }
+        }
+        line.push(b'\'');
+    }
+    OsString::from_vec(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The arguments and the names of the variables set for the escalator.
+    fn command(escalator: Escalator, vars: &[(&str, &str)]) -> (Vec<String>, Vec<String>) {
+        let vars: Vec<(OsString, OsString)> =
+            vars.iter().map(|(n, v)| (n.into(), v.into())).collect();
+        let cmd = escalator.command_with_env("/bin/prog", &["it's".into()], &vars);
+        assert_eq!(cmd.get_program(), escalator.program());
+        let args = cmd.get_args().map(|a| a.to_string_lossy().into_owned());
+        let envs = cmd
+            .get_envs()
+            .map(|(n, _)| n.to_string_lossy().into_owned());
+        (args.collect(), envs.collect())
+    }
+
+    #[test]
+    fn commands_without_vars() {
+        assert_eq!(command(Escalator::Doas, &[]).0, ["/bin/prog", "it's"]);
+        assert_eq!(command(Escalator::Sudo, &[]).0, ["--", "/bin/prog", "it's"]);
+        assert_eq!(command(Escalator::Run0, &[]).0, ["/bin/prog", "it's"]);
+        let (args, envs) = command(Escalator::Pkexec, &[]);
+        assert_eq!(args, ["/bin/prog", "it's"]);
+        assert!(envs.is_empty());
+        let (args, envs) = command(Escalator::Su, &[]);
+        assert_eq!(args, ["root", "-c", r"'/bin/prog' 'it'\''s'"]);
+        assert_eq!(envs, ["SU_UID", "SU_USER"]);
+    }
+
+    #[test]
+    fn state() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        let escalation = Escalation::new()
+            .preserve_cwd()
+            .preserve_env(["PATH"])
+            .clone();
+        let mut sent = escalation.state_args().unwrap();
+        assert_eq!(sent[0], STATE_ARG);
+        assert_eq!(sent[2], "1");
+        sent.insert(0, "argv0".into());
+        sent.push("--own".into());
+        let state = PassedState::from_args(sent).unwrap().unwrap();
+        assert_eq!(state.cwd, Some(env::current_dir().unwrap()));
+        assert_eq!(state.vars, [("PATH".into(), env::var_os("PATH").unwrap())]);
+        assert_eq!(state.args, ["--own"]);
+
+        let state = PassedState::from_args(args(&["p", STATE_ARG, "", "2", "A=1=2", "B=", "x"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.cwd, None);
+        assert_eq!(
+            state.vars,
+            [("A".into(), "1=2".into()), ("B".into(), "".into())]
+        );
+        assert_eq!(state.args, ["x"]);
+        // the directory was asked for but not passed
+        assert!(matches!(
+            state.apply(|_| false, true),
+            Err(EscalationError::StateMalformed)
+        ));
+
+        assert_eq!(
+            PassedState::from_args(args(&["p", "x", STATE_ARG])).unwrap(),
+            None
+        );
+        assert_eq!(PassedState::from_args(args(&[])).unwrap(), None);
+        for malformed in [
+            &[STATE_ARG][..],
+            &[STATE_ARG, "/"],
+            &[STATE_ARG, "/", "x"],
+            &[STATE_ARG, "/", "2", "A=1"],
+            &[STATE_ARG, "/", "1", "A"],
+        ] {
+            let argv = ["p"].iter().chain(malformed).copied().collect::<Vec<_>>();
+            assert!(matches!(
+                PassedState::from_args(args(&argv)),
+                Err(EscalationError::StateMalformed)
+            ));
+        }
+    }
+
+    #[test]
+    fn commands_with_vars() {
+        let vars = [("A", "1"), ("B=", "2"), ("C,D", "3")];
+        let (args, envs) = command(Escalator::Doas, &vars);
+        assert_eq!(args, ["/bin/prog", "it's"]);
+        assert_eq!(envs, ["A", "C,D"]);
+        let (args, envs) = command(Escalator::Sudo, &vars);
+        assert_eq!(args, ["--preserve-env=A", "--", "/bin/prog", "it's"]);
+        assert_eq!(envs, ["A"]);
+        let (args, _) = command(Escalator::Run0, &vars);
+        assert_eq!(args, ["--setenv=A", "--setenv=C,D", "/bin/prog", "it's"]);
+        let (args, _) = command(Escalator::Pkexec, &vars);
+        assert_eq!(args, ["env", "A=1", "C,D=3", "/bin/prog", "it's"]);
+        let (args, envs) = command(Escalator::Custom("env"), &vars);
+        assert_eq!(args, ["/bin/prog", "it's"]);
+        assert_eq!(envs, ["A", "C,D", "ESCALATION_UID", "ESCALATION_USER"]);
+    }
+}
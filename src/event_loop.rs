@@ -0,0 +1,479 @@
+//! A blocking event loop that multiplexes terminal input, signals,
+//! periodic ticks and the output of child processes.
+//!
+//! This is the building block for spinners, progress displays and other
+//! interactive UIs that need to react to the user while something else is
+//! going on.
+//!
+//! Example, running a command with a spinner that `q` aborts:
+//!
+//! ```
+//! use std::{io::{stdin, stdout, Write}, process::Command, time::Duration};
+//! let mut t = Term::new(stdin(), stdout())?;
+//! t.raw_mode().enable_output_processing().set(SetAction::TCSAFLUSH)?;
+//! let mut ev = EventLoop::new(t)?;
+//! ev.tick_every(Duration::from_millis(100))?;
+//! let make = ev.spawn(Command::new("make").arg("-j8"))?;
+//! let spinner = ['|', '/', '-', '\\'];
+//! let mut n = 0;
+//! ev.run(|ev, event| {
+//!     match event {
+//!         Event::Tick(_) => {
+//!             n += 1;
+//!             write!(ev.term(), "\r{} building", spinner[n % 4])?;
+//!             ev.term().flush()?;
+//!         }
+//!         Event::Key(Key::Char('q')) => ev.kill(make)?,
+//!         Event::Exit { status, .. } => {
+//!             writeln!(ev.term(), "\rmake finished: {status}")?;
+//!             ev.stop();
+//!         }
+//!         _ => (),
+//!     }
+//!     Ok(())
+//! })?;
+//! ev.into_term().reset(SetAction::TCSANOW)?;
+//! ```
+use crate::{
+    epoll::{Epoll, SignalPipe, TimerFd},
+    tty::{keys::*, Term},
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    os::fd::{AsRawFd, RawFd},
+    process::{Child, ChildStderr, ChildStdout, Command, ExitStatus, Stdio},
+    time::Duration,
+};
+
+// epoll tokens for the fixed sources; the rest are indexes into `sources`
+const TOKEN_INPUT: u64 = 0;
+const TOKEN_SIGNALS: u64 = 1;
+const TOKEN_TICK: u64 = 2;
+const TOKEN_FIRST_SOURCE: u64 = 3;
+
+/// Identifies a child process added to an [`EventLoop`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChildId(usize);
+
+/// Which output stream of a child process produced data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Events delivered to the [`EventLoop::run()`] callback.
+#[derive(Debug)]
+pub enum Event {
+    Key(Key),
+    Paste(Vec<u8>),
+    /// The terminal window changed size (`SIGWINCH`)
+    Resize {
+        cols: u16,
+        rows: u16,
+    },
+    /// A signal registered with [`EventLoop::watch_signals()`] arrived
+    Signal(libc::c_int),
+    /// The tick timer fired. Holds the number of ticks since the last
+    /// `Tick` event, which is more than one if the callback fell behind.
+    Tick(u64),
+    /// A child process wrote to its stdout or stderr
+    Output {
+        child: ChildId,
+        stream: OutputStream,
+        data: Vec<u8>,
+    },
+    /// A child process exited. All of its output has been delivered.
+    Exit {
+        child: ChildId,
+        status: ExitStatus,
+    },
+    /// An fd registered with [`EventLoop::watch_fd()`] is readable
+    Readable(RawFd),
+    /// The terminal input reached end of file
+    InputClosed,
+}
+
+enum Source {
+    Output(ChildId, OutputStream),
+    Fd(RawFd),
+    Signals(SignalPipe),
+}
+
+struct Watched {
+    child: Child,
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+    status: Option<ExitStatus>,
+}
+
+/// Blocking event loop around a [`Term`]. Events are handed to a callback
+/// that gets mutable access to the loop, so it can write to the terminal,
+/// add or kill children, and [`stop()`](Self::stop) the loop.
+///
+/// The loop does not change terminal modes; set raw mode on the `Term`
+/// before handing it over, and get it back with [`Self::into_term()`] to
+/// reset it.
+///
+/// The loop takes over the `SIGWINCH` and `SIGCHLD` handlers while it
+/// exists, so only one may exist at a time.
+pub struct EventLoop<I, O> {
+    term: Term<I, O>,
+    epoll: Epoll,
+    decoder: KeyDecoder,
+    signals: SignalPipe,
+    tick: Option<TimerFd>,
+    sources: Vec<Option<Source>>,
+    children: Vec<Option<Watched>>,
+    /// Events collected but not yet handed to the callback, kept across
+    /// calls to `run` so that none are lost when it stops early
+    pending: VecDeque<Event>,
+    input_open: bool,
+    running: bool,
+}
+impl<I: Read + AsRawFd, O: AsRawFd> EventLoop<I, O> {
+    pub fn new(term: Term<I, O>) -> io::Result<Self> {
+        let epoll = Epoll::new()?;
+        let signals = SignalPipe::new(&[libc::SIGWINCH, libc::SIGCHLD])?;
+        epoll.watch_readable(term.input().as_raw_fd(), TOKEN_INPUT, false)?;
+        epoll.watch_readable(signals.as_raw_fd(), TOKEN_SIGNALS, false)?;
+        Ok(Self {
+            term,
+            epoll,
+            decoder: KeyDecoder::new(),
+            signals,
+            tick: None,
+            sources: Vec::new(),
+            children: Vec::new(),
+            pending: VecDeque::new(),
+            input_open: true,
+            running: false,
+        })
+    }
+    /// Gives access to the [`Term`], for example to write output from the
+    /// callback.
+    pub fn term(&mut self) -> &mut Term<I, O> {
+        &mut self.term
+    }
+    /// Gives back the [`Term`], so that it can be reset.
+    pub fn into_term(self) -> Term<I, O> {
+        self.term
+    }
+    /// Delivers an [`Event::Tick`] every `period`. Replaces any previous
+    /// tick timer.
+    pub fn tick_every(&mut self, period: Duration) -> io::Result<&mut Self> {
+        let timer = TimerFd::new(period, period.max(Duration::from_nanos(1)))?;
+        self.epoll
+            .watch_readable(timer.as_raw_fd(), TOKEN_TICK, false)?;
+        if let Some(old) = self.tick.replace(timer) {
+            _ = self.epoll.unwatch(old.as_raw_fd());
+        }
+        Ok(self)
+    }
+    /// Stops delivering [`Event::Tick`].
+    pub fn stop_ticking(&mut self) -> &mut Self {
+        if let Some(old) = self.tick.take() {
+            _ = self.epoll.unwatch(old.as_raw_fd());
+        }
+        self
+    }
+    /// Delivers the given signals as [`Event::Signal`]. `SIGWINCH` and
+    /// `SIGCHLD` are always handled by the loop and may not be included.
+    pub fn watch_signals(&mut self, signals: &[libc::c_int]) -> io::Result<&mut Self> {
+        let pipe = SignalPipe::new(signals)?;
+        let fd = pipe.as_raw_fd();
+        self.add_source(fd, Source::Signals(pipe))?;
+        Ok(self)
+    }
+    /// Delivers an [`Event::Readable`] whenever `fd` has data to read. The
+    /// fd must stay open until it is removed with [`Self::unwatch_fd()`].
+    pub fn watch_fd(&mut self, fd: RawFd) -> io::Result<&mut Self> {
+        self.add_source(fd, Source::Fd(fd))?;
+        Ok(self)
+    }
+    /// Stops delivering events for an fd added with [`Self::watch_fd()`].
+    pub fn unwatch_fd(&mut self, fd: RawFd) {
+        if let Some(i) = self
+            .sources
+            .iter()
+            .position(|s| matches!(s, Some(Source::Fd(f)) if *f == fd))
+        {
+            _ = self.epoll.unwatch(fd);
+            self.sources[i] = None;
+        }
+    }
+    /// Spawns a command with its stdout and stderr piped to the loop,
+    /// which delivers them as [`Event::Output`]. Stdin is set to null so
+    /// that the child does not compete with the loop for terminal input.
+    pub fn spawn(&mut self, cmd: &mut Command) -> io::Result<ChildId> {
+        let child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        self.watch_child(child)
+    }
+    /// Adds an already spawned child. Its stdout and stderr are watched
+    /// if they were piped, and [`Event::Exit`] is delivered when it exits.
+    pub fn watch_child(&mut self, mut child: Child) -> io::Result<ChildId> {
+        let id = ChildId(self.children.len());
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        if let Some(fd) = stdout.as_ref().map(|s| s.as_raw_fd()) {
+            set_nonblocking(fd)?;
+            self.add_source(fd, Source::Output(id, OutputStream::Stdout))?;
+        }
+        if let Some(fd) = stderr.as_ref().map(|s| s.as_raw_fd()) {
+            set_nonblocking(fd)?;
+            self.add_source(fd, Source::Output(id, OutputStream::Stderr))?;
+        }
+        self.children.push(Some(Watched {
+            child,
+            stdout,
+            stderr,
+            status: None,
+        }));
+        Ok(id)
+    }
+    /// Sends `SIGKILL` to a child. Its [`Event::Exit`] is still delivered.
+    pub fn kill(&mut self, id: ChildId) -> io::Result<()> {
+        match self.children.get_mut(id.0).and_then(|c| c.as_mut()) {
+            Some(w) => w.child.kill(),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+    /// Returns the process id of a child that has not been reported as
+    /// exited, for sending other signals.
+    pub fn pid(&self, id: ChildId) -> Option<u32> {
+        self.children
+            .get(id.0)
+            .and_then(|c| c.as_ref())
+            .map(|w| w.child.id())
+    }
+    /// Makes [`Self::run()`] return after the current callback. Events
+    /// that were already collected are delivered by the next `run`.
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+    /// Waits for events and hands them to `f` until [`Self::stop()`] is
+    /// called or the callback returns an error. If nothing remains to wait
+    /// for, `run` returns as well. Events that were not handed over yet
+    /// are kept for the next call.
+    pub fn run(&mut self, mut f: impl FnMut(&mut Self, Event) -> io::Result<()>) -> io::Result<()> {
+        self.running = true;
+        let mut tokens = Vec::new();
+        let mut events = Vec::new();
+        while self.running {
+            if let Some(event) = self.pending.pop_front() {
+                f(self, event)?;
+                continue;
+            }
+            if !self.input_open
+                && self.tick.is_none()
+                && self.sources.iter().all(|s| s.is_none())
+                && self.children.iter().all(|c| c.is_none())
+            {
+                break;
+            }
+            self.epoll.wait(&mut tokens, None)?;
+            for token in tokens.iter() {
+                self.collect(*token, &mut events)?;
+            }
+            self.collect_exits(&mut events)?;
+            self.pending.extend(events.drain(..));
+        }
+        Ok(())
+    }
+    fn add_source(&mut self, fd: RawFd, source: Source) -> io::Result<()> {
+        let index = self.sources.len();
+        self.epoll
+            .watch_readable(fd, TOKEN_FIRST_SOURCE + index as u64, false)?;
+        self.sources.push(Some(source));
+        Ok(())
+    }
+    /// Turns a ready token into events.
+    fn collect(&mut self, token: u64, events: &mut Vec<Event>) -> io::Result<()> {
+        match token {
+            TOKEN_INPUT => {
+                let mut buf = [0u8; 256];
+                match self.term.read(&mut buf) {
+                    Ok(0) => {
+                        _ = self.epoll.unwatch(self.term.input().as_raw_fd());
+                        self.input_open = false;
+                        events.push(Event::InputClosed);
+                    }
+                    Ok(n) => self.decoder.feed(&buf[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                    Err(e) => return Err(e),
+                }
+                while let Some(ev) = self.decoder.next_event() {
+                    events.push(match ev {
+                        TermEvent::Key(k) => Event::Key(k),
+                        TermEvent::Paste(p) => Event::Paste(p),
+                        TermEvent::Resize { cols, rows } => Event::Resize { cols, rows },
+                    });
+                }
+            }
+            TOKEN_SIGNALS => {
+                // SIGCHLD is handled by collect_exits, which runs every time
+                if self.signals.drain()?.contains(&libc::SIGWINCH) {
+                    let (cols, rows) = self.term.size()?;
+                    events.push(Event::Resize { cols, rows });
+                }
+            }
+            TOKEN_TICK => {
+                if let Some(n) = self.tick.as_ref().map(|t| t.read()).transpose()?.flatten() {
+                    events.push(Event::Tick(n));
+                }
+            }
+            _ => {
+                let index = (token - TOKEN_FIRST_SOURCE) as usize;
+                match self.sources.get(index).and_then(|s| s.as_ref()) {
+                    Some(Source::Fd(fd)) => events.push(Event::Readable(*fd)),
+                    Some(Source::Signals(pipe)) => {
+                        events.extend(pipe.drain()?.into_iter().map(Event::Signal));
+                    }
+                    Some(Source::Output(id, stream)) => {
+                        let (id, stream) = (*id, *stream);
+                        if self.read_output(id, stream, events)? {
+                            self.sources[index] = None;
+                        }
+                    }
+                    None => (),
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Reads whatever a child's pipe has available. Returns true and
+    /// closes the pipe on end of file.
+    fn read_output(
+        &mut self,
+        id: ChildId,
+        stream: OutputStream,
+        events: &mut Vec<Event>,
+    ) -> io::Result<bool> {
+        let Some(w) = self.children.get_mut(id.0).and_then(|c| c.as_mut()) else {
+            return Ok(true);
+        };
+        let mut buf = [0u8; 4096];
+        let mut data = Vec::new();
+        let result = match stream {
+            OutputStream::Stdout => read_available(w.stdout.as_mut(), &mut buf, &mut data),
+            OutputStream::Stderr => read_available(w.stderr.as_mut(), &mut buf, &mut data),
+        };
+        if !data.is_empty() {
+            events.push(Event::Output {
+                child: id,
+                stream,
+                data,
+            });
+        }
+        let eof = result?;
+        if eof {
+            let fd = match stream {
+                OutputStream::Stdout => w.stdout.as_ref().map(|s| s.as_raw_fd()),
+                OutputStream::Stderr => w.stderr.as_ref().map(|s| s.as_raw_fd()),
+            };
+            if let Some(fd) = fd {
+                _ = self.epoll.unwatch(fd);
+            }
+            match stream {
+                OutputStream::Stdout => w.stdout = None,
+                OutputStream::Stderr => w.stderr = None,
+            }
+        }
+        Ok(eof)
+    }
+    /// Reaps exited children, and reports the exit once all of the output
+    /// has been read.
+    fn collect_exits(&mut self, events: &mut Vec<Event>) -> io::Result<()> {
+        for (i, slot) in self.children.iter_mut().enumerate() {
+            let Some(w) = slot.as_mut() else { continue };
+            if w.status.is_none() {
+                w.status = w.child.try_wait()?;
+            }
+            if let (Some(status), None, None) = (w.status, &w.stdout, &w.stderr) {
+                events.push(Event::Exit {
+                    child: ChildId(i),
+                    status,
+                });
+                *slot = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads from a nonblocking pipe until it would block. Returns true on end
+/// of file.
+fn read_available(
+    pipe: Option<&mut impl Read>,
+    buf: &mut [u8],
+    data: &mut Vec<u8>,
+) -> io::Result<bool> {
+    let Some(pipe) = pipe else { return Ok(true) };
+    loop {
+        match pipe.read(buf) {
+            Ok(0) => return Ok(true),
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tty::openpty;
+
+    #[test]
+    fn stop_keeps_the_rest_of_the_batch() {
+        let (_master, slave) = openpty().unwrap();
+        let term = Term::new(slave.try_clone().unwrap(), slave).unwrap();
+        let mut ev = EventLoop::new(term).unwrap();
+        let first = ev.spawn(&mut Command::new("true")).unwrap();
+        let second = ev.spawn(&mut Command::new("true")).unwrap();
+        // let both exit, so that their exits are collected together
+        std::thread::sleep(Duration::from_millis(200));
+        // fails the test instead of hanging if an exit is lost
+        ev.tick_every(Duration::from_millis(100)).unwrap();
+        let mut ticks = 0;
+        let mut exits = Vec::new();
+        while exits.len() < 2 {
+            ev.run(|ev, event| {
+                match event {
+                    Event::Exit { child, .. } => {
+                        exits.push(child);
+                        ev.stop();
+                    }
+                    Event::Tick(n) => {
+                        ticks += n;
+                        if ticks > 50 {
+                            return Err(io::Error::new(
+                                io::ErrorKind::TimedOut,
+                                "an exit was not delivered",
+                            ));
+                        }
+                    }
+                    _ => (),
+                }
+                Ok(())
+            })
+            .expect("Should deliver both exits");
+        }
+        exits.sort_by_key(|c| c.0);
+        assert_eq!(exits, [first, second]);
+    }
+}
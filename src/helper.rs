@@ -0,0 +1,664 @@
+//! A helper process running as root, which carries out requests from the
+//! unprivileged main process, so that only the code handling them runs
+//! with privileges.
+use crate::{escalate::PassedState, Escalation, Escalator};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fs::{File, OpenOptions, Permissions},
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{
+            ffi::{OsStrExt, OsStringExt},
+            fs::{FileTypeExt, OpenOptionsExt, PermissionsExt},
+            net::UnixStream,
+            process::{CommandExt, ExitStatusExt},
+        },
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+};
+
+/// Followed by the descriptor of the socket, the last argument of the
+/// program started by [`Escalation::spawn_helper()`], which tells it to
+/// serve requests instead of running normally. An argument gets through
+/// every escalator, unlike an environment variable.
+const HELPER_ARG: &str = "--privileged-helper-fd=";
+/// Messages larger than this are treated as corrupt
+#[cfg(not(test))]
+const MAX_MESSAGE: usize = 256 << 20;
+/// Small enough for tests to exceed
+#[cfg(test)]
+const MAX_MESSAGE: usize = 1 << 20;
+
+// the first byte of a request
+const WRITE_FILE: u8 = 1;
+const RUN: u8 = 2;
+const CALL: u8 = 3;
+// the first byte of a reply
+const OK: u8 = 0;
+const ERR: u8 = 1;
+
+/// Error kinds sent in an `ERR` reply, by their index. Others are sent as
+/// `Other`.
+const ERROR_KINDS: [io::ErrorKind; 14] = [
+    io::ErrorKind::Other,
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::AlreadyExists,
+    io::ErrorKind::InvalidInput,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::Unsupported,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::WouldBlock,
+    io::ErrorKind::UnexpectedEof,
+    io::ErrorKind::BrokenPipe,
+    io::ErrorKind::OutOfMemory,
+    io::ErrorKind::ConnectionRefused,
+];
+
+/// A connection to a copy of the current program running as root,
+/// started with [`Escalation::spawn_helper()`]. The escalator is only run
+/// once, and the helper serves requests until this is dropped. The
+/// program has to call [`serve_if_requested()`] before doing anything
+/// else, which is where the helper handles the requests. Example:
+///
+/// ```
+/// fn main() -> io::Result<()> {
+///     serve_if_requested(|name, data| match name {
+///         "hostname" => {
+///             std::fs::write("/etc/hostname", data)?;
+///             Ok(Vec::new())
+///         }
+///         _ => Err(io::Error::new(io::ErrorKind::Unsupported, name)),
+///     });
+///     let config = build_config();
+///     let mut root = Escalation::new().spawn_helper()?;
+///     root.write_file("/etc/mytool.conf", config, 0o644)?;
+///     root.call("hostname", b"build-01")?;
+///     let output = root.run(Command::new("systemctl").args(["restart", "mytool"]))?;
+///     Ok(())
+/// }
+/// ```
+pub struct PrivilegedHelper {
+    child: Child,
+    stream: UnixStream,
+}
+impl PrivilegedHelper {
+    /// Runs `cmd` with one end of a socket as its descriptor `fd`, and
+    /// waits for it to report in.
+    pub(crate) fn spawn(mut cmd: Command, fd: RawFd) -> io::Result<Self> {
+        let (stream, theirs) = UnixStream::pair()?;
+        let theirs = OwnedFd::from(theirs);
+        if fd == 1 {
+            cmd.stdout(theirs.try_clone()?);
+        } else {
+            let raw = theirs.as_raw_fd();
+            unsafe {
+                cmd.pre_exec(move || {
+                    // the copy made by dup2 stays open across exec
+                    let r = match raw == fd {
+                        true => libc::fcntl(fd, libc::F_SETFD, 0),
+                        false => libc::dup2(raw, fd),
+                    };
+                    match r < 0 {
+                        true => Err(io::Error::last_os_error()),
+                        false => Ok(()),
+                    }
+                });
+            }
+        }
+        let child = cmd.spawn();
+        // our copies of the helper's end would hide it exiting
+        drop(cmd);
+        drop(theirs);
+        let child = child?;
+        let mut helper = Self { child, stream };
+        // the escalator may ask for a password before the helper runs
+        match receive(&mut helper.stream)? {
+            Some(reply) if reply == [OK] => Ok(helper),
+            Some(_) => Err(invalid("unexpected greeting from the helper")),
+            None => {
+                let status = helper.child.wait()?;
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("the helper exited before it was ready ({status})"),
+                ))
+            }
+        }
+    }
+    /// Writes `contents` to `path`, creating or truncating it, and sets
+    /// its permissions to `mode`.
+    pub fn write_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+        mode: u32,
+    ) -> io::Result<()> {
+        let mut msg = Message::new(WRITE_FILE);
+        msg.bytes(path.as_ref().as_os_str().as_bytes())
+            .u32(mode)
+            .bytes(contents.as_ref());
+        self.request(&msg)?;
+        Ok(())
+    }
+    /// Runs the program of `cmd` with its arguments, working directory and
+    /// environment changes, and collects its output. Stdin is null.
+    /// `Command` cannot tell whether [`Command::env_clear()`] was called
+    /// or what its stdio settings are, so those have no effect; use
+    /// [`Self::run_with()`] for them.
+    pub fn run(&mut self, cmd: &Command) -> io::Result<Output> {
+        self.run_with(cmd, &RunOptions::new())
+    }
+    /// Like [`Self::run()`], with the settings in `options`. Example:
+    ///
+    /// ```
+    /// let output = root.run_with(
+    ///     Command::new("chpasswd").env("LANG", "C"),
+    ///     RunOptions::new().env_clear().stdin(format!("{user}:{password}\n")),
+    /// )?;
+    /// ```
+    pub fn run_with(&mut self, cmd: &Command, options: &RunOptions) -> io::Result<Output> {
+        let mut msg = Message::new(RUN);
+        msg.bytes(cmd.get_program().as_bytes());
+        msg.u32(cmd.get_args().len() as u32);
+        for arg in cmd.get_args() {
+            msg.bytes(arg.as_bytes());
+        }
+        let dir = cmd.get_current_dir().map(Path::as_os_str);
+        msg.bytes(dir.unwrap_or_default().as_bytes());
+        msg.u8(options.env_clear as u8);
+        msg.u32(cmd.get_envs().len() as u32);
+        for (name, value) in cmd.get_envs() {
+            msg.bytes(name.as_bytes());
+            match value {
+                Some(value) => msg.u8(1).bytes(value.as_bytes()),
+                None => msg.u8(0),
+            };
+        }
+        match &options.stdin {
+            Some(input) => msg.u8(1).bytes(input),
+            None => msg.u8(0),
+        };
+        let reply = self.request(&msg)?;
+        let mut fields = Fields(&reply);
+        Ok(Output {
+            status: ExitStatus::from_raw(fields.u32()? as i32),
+            stdout: fields.bytes()?.to_vec(),
+            stderr: fields.bytes()?.to_vec(),
+        })
+    }
+    /// Passes `data` to the handler given to [`serve_if_requested()`]
+    /// along with `name`, and returns what it returns.
+    pub fn call(&mut self, name: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut msg = Message::new(CALL);
+        msg.bytes(name.as_bytes()).bytes(data);
+        let reply = self.request(&msg)?;
+        Ok(Fields(&reply).bytes()?.to_vec())
+    }
+    /// Stops the helper and waits for it to exit, which dropping also
+    /// does.
+    pub fn close(mut self) -> io::Result<ExitStatus> {
+        self.finish()
+    }
+    fn finish(&mut self) -> io::Result<ExitStatus> {
+        // the helper exits when it reads end of file
+        _ = self.stream.shutdown(std::net::Shutdown::Both);
+        self.child.wait()
+    }
+    /// Sends a request, and returns the fields of the reply after its
+    /// status.
+    fn request(&mut self, msg: &Message) -> io::Result<Vec<u8>> {
+        msg.send(&mut self.stream)?;
+        let mut reply = receive(&mut self.stream)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "the helper exited"))?;
+        let mut fields = Fields(&reply);
+        match fields.u8()? {
+            OK => Ok(reply.split_off(1)),
+            ERR => {
+                let code = fields.u32()? as i32;
+                let kind = ERROR_KINDS.get(fields.u8()? as usize);
+                let message = String::from_utf8_lossy(fields.bytes()?).into_owned();
+                // the errno tells apart more kinds than the table does
+                let kind = match code {
+                    0 => kind.copied().unwrap_or(io::ErrorKind::Other),
+                    code => io::Error::from_raw_os_error(code).kind(),
+                };
+                Err(io::Error::new(kind, message))
+            }
+            tag => Err(invalid(format!("unknown reply {tag}"))),
+        }
+    }
+}
+impl Drop for PrivilegedHelper {
+    fn drop(&mut self) {
+        _ = self.finish();
+    }
+}
+
+/// Settings for [`PrivilegedHelper::run_with()`] that cannot be read back
+/// from a `Command`.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    env_clear: bool,
+    stdin: Option<Vec<u8>>,
+}
+impl RunOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Starts the command with an empty environment, plus the variables
+    /// set on the `Command`, as [`Command::env_clear()`] does.
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env_clear = true;
+        self
+    }
+    /// Feeds `input` to the stdin of the command, instead of null.
+    pub fn stdin(&mut self, input: impl Into<Vec<u8>>) -> &mut Self {
+        self.stdin = Some(input.into());
+        self
+    }
+}
+
+impl Escalation {
+    /// Starts a [`PrivilegedHelper`] through the escalator, instead of
+    /// replacing the current process with it. The options for the
+    /// escalator, environment, working directory and confirmation apply
+    /// as for [`Self::ensure_root()`].
+    pub fn spawn_helper(&self) -> Result<PrivilegedHelper, crate::EscalationError> {
+        let escalator = self.chosen_escalator()?;
+        let fd = socket_fd(escalator);
+        let cmd = self.reexec_command(escalator, Some(vec![format!("{HELPER_ARG}{fd}").into()]))?;
+        PrivilegedHelper::spawn(cmd, fd)
+            .map_err(|error| crate::EscalationError::ExecFailed { escalator, error })
+    }
+}
+
+/// The descriptor that the helper gets its end of the socket on. sudo,
+/// doas and pkexec close the descriptors above stderr, and run0 only
+/// forwards the standard ones, so with them it is stdout, which leaves
+/// stdin to the password prompt. su and custom programs keep them, so
+/// there it is a descriptor of its own.
+fn socket_fd(escalator: Escalator) -> RawFd {
+    match escalator {
+        Escalator::Su | Escalator::Custom(_) => 3,
+        _ => 1,
+    }
+}
+
+/// If this process is a helper started by [`Escalation::spawn_helper()`],
+/// serves requests until the main process closes the connection, then
+/// exits. Otherwise returns immediately. Requests made with
+/// [`PrivilegedHelper::call()`] are passed to `handler` with their name
+/// and data. Call this first thing in `main`, so that nothing else runs
+/// as root. Output printed to stdout by the handler goes to stderr.
+pub fn serve_if_requested<F>(handler: F)
+where
+    F: FnMut(&str, &[u8]) -> io::Result<Vec<u8>>,
+{
+    let Some(arg) = env::args_os().last() else {
+        return;
+    };
+    let Some(fd) = arg.to_str().and_then(|a| a.strip_prefix(HELPER_ARG)) else {
+        return;
+    };
+    let code = match fd
+        .parse()
+        .map_err(invalid)
+        .and_then(|fd| serve(fd, handler))
+    {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("privileged helper: {e}");
+            1
+        }
+    };
+    std::process::exit(code);
+}
+
+fn serve<F>(fd: RawFd, mut handler: F) -> io::Result<()>
+where
+    F: FnMut(&str, &[u8]) -> io::Result<Vec<u8>>,
+{
+    // the handler is trusted with whatever the main process sends, so
+    // every variable passed along is taken
+    if let Some(state) = PassedState::from_args(env::args_os())? {
+        state.apply(|_| true, true)?;
+    }
+    let mut stream = take_socket(fd)?;
+    Message::new(OK).send(&mut stream)?;
+    while let Some(request) = receive(&mut stream)? {
+        let reply = handle(&request, &mut handler)
+            .and_then(|reply| match reply.0.len() > MAX_MESSAGE {
+                // failing to send it would end the connection
+                true => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the reply of {} bytes is too large to send", reply.0.len()),
+                )),
+                false => Ok(reply),
+            })
+            .unwrap_or_else(|e| {
+                let kind = ERROR_KINDS.iter().position(|k| *k == e.kind());
+                let mut msg = Message::new(ERR);
+                msg.u32(e.raw_os_error().unwrap_or(0) as u32)
+                    .u8(kind.unwrap_or(0) as u8)
+                    .bytes(e.to_string().as_bytes());
+                msg
+            });
+        reply.send(&mut stream)?;
+    }
+    Ok(())
+}
+
+/// Takes the socket from descriptor `fd`, and points stdin and stdout
+/// elsewhere, so that output from the handler cannot corrupt the protocol.
+fn take_socket(fd: RawFd) -> io::Result<UnixStream> {
+    if fd < 1 || fd == 2 {
+        return Err(invalid(format!("cannot serve on descriptor {fd}")));
+    }
+    // a copy that is closed on exec, so that commands run for requests do
+    // not get it
+    let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) };
+    if copy < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(copy) };
+    if !File::from(socket.try_clone()?)
+        .metadata()?
+        .file_type()
+        .is_socket()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("descriptor {fd} is not a socket"),
+        ));
+    }
+    let null = File::open("/dev/null")?;
+    unsafe {
+        if libc::dup2(null.as_raw_fd(), 0) < 0 || libc::dup2(2, 1) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if fd > 2 {
+            libc::close(fd);
+        }
+    }
+    Ok(UnixStream::from(socket))
+}
+
+fn handle<F>(request: &[u8], handler: &mut F) -> io::Result<Message>
+where
+    F: FnMut(&str, &[u8]) -> io::Result<Vec<u8>>,
+{
+    let mut fields = Fields(request);
+    let mut reply = Message::new(OK);
+    match fields.u8()? {
+        WRITE_FILE => {
+            let path = PathBuf::from(fields.os_string()?);
+            let mode = fields.u32()?;
+            let contents = fields.bytes()?;
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(mode)
+                .open(&path)?;
+            // the mode only applies to new files
+            file.set_permissions(Permissions::from_mode(mode))?;
+            file.write_all(contents)?;
+        }
+        RUN => {
+            let mut cmd = Command::new(fields.os_string()?);
+            for _ in 0..fields.u32()? {
+                cmd.arg(fields.os_string()?);
+            }
+            let dir = fields.os_string()?;
+            if !dir.is_empty() {
+                cmd.current_dir(dir);
+            }
+            // before the variables, which it would remove
+            if fields.u8()? != 0 {
+                cmd.env_clear();
+            }
+            for _ in 0..fields.u32()? {
+                let name = fields.os_string()?;
+                match fields.u8()? {
+                    0 => cmd.env_remove(name),
+                    _ => cmd.env(name, fields.os_string()?),
+                };
+            }
+            let input = match fields.u8()? {
+                0 => None,
+                _ => Some(fields.bytes()?),
+            };
+            let output = output_with_input(&mut cmd, input)?;
+            reply
+                .u32(output.status.into_raw() as u32)
+                .bytes(&output.stdout)
+                .bytes(&output.stderr);
+        }
+        CALL => {
+            let name = std::str::from_utf8(fields.bytes()?).map_err(invalid)?;
+            let data = fields.bytes()?;
+            reply.bytes(&handler(name, data)?);
+        }
+        tag => return Err(invalid(format!("unknown request {tag}"))),
+    }
+    Ok(reply)
+}
+
+/// Runs `cmd` and collects its output, with `input` as its stdin, or null.
+fn output_with_input(cmd: &mut Command, input: Option<&[u8]>) -> io::Result<Output> {
+    let Some(input) = input else {
+        return cmd.stdin(Stdio::null()).output();
+    };
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("Should have piped stdin");
+    std::thread::scope(|scope| {
+        // written from another thread, so that a command that writes a
+        // lot before reading cannot block both sides; a command that
+        // exits without reading it all is not an error
+        scope.spawn(move || _ = stdin.write_all(input));
+        child.wait_with_output()
+    })
+}
+
+/// A request or reply: a tag byte followed by fields, sent with a length
+/// prefix. Integers are little endian, and byte strings are prefixed with
+/// their length.
+struct Message(Vec<u8>);
+impl Message {
+    fn new(tag: u8) -> Self {
+        Self(vec![tag])
+    }
+    fn u8(&mut self, value: u8) -> &mut Self {
+        self.0.push(value);
+        self
+    }
+    fn u32(&mut self, value: u32) -> &mut Self {
+        self.0.extend(value.to_le_bytes());
+        self
+    }
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.u32(bytes.len() as u32);
+        self.0.extend(bytes);
+        self
+    }
+    fn send(&self, w: &mut impl Write) -> io::Result<()> {
+        if self.0.len() > MAX_MESSAGE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "request too large for the helper",
+            ));
+        }
+        w.write_all(&(self.0.len() as u32).to_le_bytes())?;
+        w.write_all(&self.0)?;
+        w.flush()
+    }
+}
+
+/// Reads a message, or returns `None` at end of file.
+fn receive(r: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match r.read_exact(&mut len) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len == 0 || len > MAX_MESSAGE {
+        return Err(invalid(format!("bad message length {len}")));
+    }
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+/// Reads the fields of a message in order.
+struct Fields<'a>(&'a [u8]);
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if n > self.0.len() {
+            return Err(invalid("truncated message"));
+        }
+        let (field, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(field)
+    }
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(
+            bytes.try_into().expect("Should have taken 4 bytes"),
+        ))
+    }
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+    fn os_string(&mut self) -> io::Result<OsString> {
+        Ok(OsStr::from_bytes(self.bytes()?).to_os_string())
+    }
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_fields() {
+        let mut msg = Message::new(RUN);
+        msg.u8(7).u32(0x1234_5678).bytes(b"abc").bytes(b"");
+        let mut sent = Vec::new();
+        msg.send(&mut sent).expect("Should write to a vec");
+        let mut r = &sent[..];
+        let received = receive(&mut r)
+            .expect("Should read the message")
+            .expect("Should not be at end of file");
+        assert!(receive(&mut r).expect("Should be at end of file").is_none());
+        let mut fields = Fields(&received);
+        assert_eq!(fields.u8().unwrap(), RUN);
+        assert_eq!(fields.u8().unwrap(), 7);
+        assert_eq!(fields.u32().unwrap(), 0x1234_5678);
+        assert_eq!(fields.os_string().unwrap(), "abc");
+        assert_eq!(fields.bytes().unwrap(), b"");
+        assert!(fields.u8().is_err());
+    }
+
+    #[test]
+    fn malformed_messages() {
+        // a byte string longer than what is left
+        let mut fields = Fields(&[5, 0, 0, 0, b'a']);
+        assert_eq!(
+            fields.bytes().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(Fields(&[1, 2]).u32().is_err());
+        for len in [0, MAX_MESSAGE as u32 + 1] {
+            let e = receive(&mut &len.to_le_bytes()[..]).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        }
+        let e = receive(&mut &[3, 0, 0, 0, 1][..]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    /// Serves as the helper when `custom_escalator` runs this binary, and
+    /// does nothing otherwise.
+    #[test]
+    fn serve() {
+        serve_if_requested(|name, data| match name {
+            "echo" => {
+                // goes to stderr, not the socket
+                println!("echoing");
+                Ok(data.to_vec())
+            }
+            _ => Err(io::Error::new(io::ErrorKind::Unsupported, "no such call")),
+        });
+    }
+
+    #[test]
+    fn custom_escalator() {
+        let exe = env::current_exe().expect("Should find the test binary");
+        let args: Vec<OsString> = ["helper::tests::serve", "--exact", "--"]
+            .into_iter()
+            .map(OsString::from)
+            .chain([format!("{HELPER_ARG}3").into()])
+            .collect();
+        let mut cmd = Escalator::Custom("env").command(exe, &args);
+        // the test harness reports on stdout
+        cmd.stdout(Stdio::null());
+        let mut helper = PrivilegedHelper::spawn(cmd, 3).expect("Should start the helper");
+
+        assert_eq!(helper.call("echo", b"data").unwrap(), b"data");
+        let e = helper.call("other", b"").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        assert!(e.to_string().contains("no such call"), "{e}");
+        let e = helper
+            .write_file("/nonexistent/dir/file", "", 0o600)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+        let path = env::temp_dir().join(format!("helper-test-{}", std::process::id()));
+        helper.write_file(&path, "contents", 0o640).unwrap();
+        let meta = path.metadata().unwrap();
+        assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+        assert_eq!(std::fs::read(&path).unwrap(), b"contents");
+        std::fs::remove_file(&path).unwrap();
+
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", "cat; echo \"${HOME-unset} $X\"; pwd; exit 3"])
+            .env("X", "set")
+            .current_dir("/");
+        let output = helper.run(&cmd).unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert!(output.stdout.ends_with(b" set\n/\n"));
+        assert!(!output.stdout.starts_with(b"unset"));
+        let output = helper
+            .run_with(&cmd, RunOptions::new().env_clear().stdin("input\n"))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "input\nunset set\n/\n"
+        );
+
+        // output too large for a reply fails the request, not the helper
+        let mut cmd = Command::new("head");
+        cmd.args(["-c", &MAX_MESSAGE.to_string(), "/dev/zero"]);
+        let e = helper.run(&cmd).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("too large"), "{e}");
+        assert_eq!(helper.call("echo", b"still").unwrap(), b"still");
+
+        assert!(helper.close().unwrap().success());
+    }
+}
@@ -12,11 +12,40 @@ use std::{
     process::Command,
     env::{args_os, ArgsOs},
 };
+mod caps;
+mod epoll;
+mod escalate;
+mod event_loop;
+mod helper;
+mod privileges;
+#[cfg(feature = "async")]
+mod reactor;
+mod reexec;
 mod tty;
-pub use tty::{password::*, SetAction, Term};
-
-pub type DoasUser = String;
-pub type DoasUid = u32;
+mod user;
+pub use caps::{has_capability, CapSet, Capabilities, Capability};
+pub use escalate::{ensure_root, Escalation, EscalationError, Escalator};
+pub use event_loop::{ChildId, Event, EventLoop, OutputStream};
+pub use helper::{serve_if_requested, PrivilegedHelper, RunOptions};
+pub use privileges::{
+    drop_privileges, switch_to_user, user_command, PrivilegeDrop, UserSwitch,
+};
+#[cfg(feature = "async")]
+pub use reactor::{block_on, sleep, wait_child, ChildExit, Interval, Sleep};
+pub use reexec::{reexec_self, Reexec};
+#[cfg(feature = "async")]
+pub use tty::stream::{EventStream, NextEvent};
+pub use tty::{
+    flags::{
+        ControlChar, ControlFlags, Flags, InputFlags, LocalFlags, OutputFlags, TermiosBuilder,
+    },
+    keys::{Key, KeyDecoder, TermEvent},
+    password::*,
+    serial::{FlowControl, ModemLines, Parity, SerialConfig, StopBits},
+    settings::{Change, FlagGroup, Termios, TermiosDiff},
+    SetAction, Term,
+};
+pub use user::InvokingUser;
 
 #[derive(Debug)]
 pub struct Keystroke([u8; 4]);
@@ -102,6 +131,47 @@ pub fn prompt_yn<I: Read, O: AsRawFd>(
     }
 }
 
+/// Like [`prompt_yn()`], but asks on `term` instead of stdout, and
+/// reports errors instead of panicking. Ctrl-c returns an error of kind
+/// `Interrupted`, and the end of the input one of kind `UnexpectedEof`.
+pub fn try_prompt_yn<I: Read, O: AsRawFd + Write>(
+    term: &mut Term<I, O>,
+    default: Option<bool>,
+    msg: impl Display,
+) -> io::Result<bool> {
+    loop {
+        if let Some(default) = default {
+            if default {
+                write!(term, "{} [yn] (default y)? ", msg)?;
+            } else {
+                write!(term, "{} [yn] (default n)? ", msg)?;
+            }
+        } else {
+            write!(term, "{} [yn]? ", msg)?;
+        }
+        term.flush()?;
+        let keystroke = keystroke(term)?;
+        if keystroke.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if keystroke.is_ctrl_c() {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+        if keystroke.is_enter() {
+            if let Some(default) = default {
+                return Ok(default);
+            }
+        }
+        if let Some(c) = keystroke.as_char() {
+            match c {
+                'y' | 'Y' => return Ok(true),
+                'n' | 'N' => return Ok(false),
+                _ => continue,
+            }
+        }
+    }
+}
+
 pub fn press_any_key<I: Read, O: AsRawFd + Write>(term: &mut Term<I, O>) {
     writeln!(term, "Press any key to continue.");
     _ = term.flush();
@@ -174,44 +244,59 @@ pub fn is_root_user() -> bool {
 }
 
 // Checks euid, execs this process with doas if not root.
-// Sets DOAS_UID to the current euid prior to exec.
-// Returns the DOAS_USER and DOAS_UID env variable after exec
-pub fn ensure_running_doas() -> Result<(DoasUser, DoasUid), std::io::Error> {
-    let euid = nix::unistd::geteuid();
-    if euid.is_root() {
-        let user = std::env::var("DOAS_USER").expect("Should be running with doas");
-        let uid = std::env::var("DOAS_UID")
-            .expect("Should have found DOAS_UID environment variable")
-            .parse::<u32>()
-            .expect("DOAS_UID environment variable should be parseable as an integer");
-        return Ok((user, uid));
-    }
-    unsafe {
-        std::env::set_var("DOAS_UID", euid.to_string());
-    }
-    let current_exe = std::env::current_exe()?;
-    doas(current_exe, args_os()).expect("Unable to execute doas");
-    unreachable!()
+// Returns the user named by DOAS_USER after exec
+pub fn ensure_running_doas() -> Result<InvokingUser, EscalationError> {
+    Escalation::new().escalator(Escalator::Doas).ensure_root()
 }
 
-fn doas(executable: PathBuf, cli_args: ArgsOs) -> Result<(), std::ffi::NulError> {
-    // TODO: make this configurable? rely on PATH instead?
-    let doas_bin = CString::new(b"doas".to_vec())?;
-
-    let cstring_args: Vec<CString> = cli_args
-        .map(|x| CString::new(x.as_bytes()))
-        .flatten()
-        .collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
 
-    let executable = CString::new(executable.into_os_string().into_vec())?;
+    /// Answers `try_prompt_yn` with `keys`, and returns the result along with
+    /// everything it wrote.
+    fn answer_yn(
+        default: Option<bool>,
+        keys: &'static [&'static [u8]],
+    ) -> (io::Result<bool>, String) {
+        let (mut master, slave) = tty::openpty().unwrap();
+        let mut term = Term::new(slave.try_clone().unwrap(), slave).unwrap();
+        let typist = thread::spawn(move || {
+            let mut out = Vec::new();
+            let mut buf = [0u8; 256];
+            for key in keys {
+                while !out.ends_with(b"? ") {
+                    let n = master.read(&mut buf).unwrap();
+                    out.extend_from_slice(&buf[..n]);
+                }
+                // the prompt is written before raw mode flushes the input
+                thread::sleep(std::time::Duration::from_millis(50));
+                master.write_all(key).unwrap();
+                out.push(b'|');
+            }
+            (master, out)
+        });
+        let answer = try_prompt_yn(&mut term, default, "Continue");
+        let (master, out) = typist.join().unwrap();
+        drop(master);
+        (answer, String::from_utf8(out).unwrap())
+    }
 
-    // build &CStr args for the exec call
-    let mut args: Vec<&CStr> = Vec::new();
-    args.push(&executable); // expected to be the name of the executable
-    for arg in cstring_args.iter() {
-        args.push(arg.as_c_str());
-    }
-    // exec
-    nix::unistd::execvp(&doas_bin, args.as_slice()).expect("Should have execed a new process");
-    unreachable!()
+    #[test]
+    fn yes_or_no() {
+        let (answer, out) = answer_yn(Some(false), &[b"x", b"Y"]);
+        assert!(answer.unwrap());
+        assert_eq!(
+            out,
+            "Continue [yn] (default n)? |Continue [yn] (default n)? |"
+        );
+        assert!(!answer_yn(Some(false), &[b"\r"]).0.unwrap());
+        assert!(answer_yn(Some(true), &[b"\r"]).0.unwrap());
+        let (answer, out) = answer_yn(None, &[b"\r", b"n"]);
+        assert!(!answer.unwrap());
+        assert_eq!(out, "Continue [yn]? |Continue [yn]? |");
+        let e = answer_yn(Some(true), &[b"\x03"]).0.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+    }
 }
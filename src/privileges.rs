@@ -0,0 +1,403 @@
+//! Doing work as the user who invoked the program through an escalator,
+//! while running as root.
+use crate::{
+    caps::{self, CapSet, Capabilities, Capability},
+    InvokingUser,
+};
+use nix::unistd::{self, Gid, Uid};
+use std::{
+    ffi::OsStr,
+    io,
+    os::unix::process::CommandExt,
+    process::Command,
+};
+
+/// The effective ids of the process while it acts as another user,
+/// returned by [`switch_to_user()`]. The saved ids are restored when this
+/// is dropped, or earlier with [`Self::restore()`].
+#[must_use = "the user is switched back when this is dropped"]
+pub struct UserSwitch {
+    euid: Uid,
+    egid: Gid,
+    groups: Vec<Gid>,
+    restored: bool,
+}
+impl UserSwitch {
+    /// Switches back, reporting errors that dropping would ignore.
+    pub fn restore(mut self) -> io::Result<()> {
+        self.restore_ids()
+    }
+    fn restore_ids(&mut self) -> io::Result<()> {
+        self.restored = true;
+        // regain root first, which is needed to change the groups
+        unistd::seteuid(self.euid)?;
+        unistd::setegid(self.egid)?;
+        unistd::setgroups(&self.groups)?;
+        Ok(())
+    }
+}
+impl Drop for UserSwitch {
+    fn drop(&mut self) {
+        if !self.restored {
+            // failing leaves the process with fewer privileges, not more
+            _ = self.restore_ids();
+        }
+    }
+}
+
+/// Temporarily sets the effective uid, effective gid and supplementary
+/// groups to those of `user`, usually the one returned by
+/// [`crate::ensure_root()`], so that files are created and accessed as
+/// that user. The real and saved uid stay root, so this is not a security
+/// boundary; use [`user_command()`] for work that must not run as root.
+/// The ids apply to every thread in the process. Example:
+///
+/// ```
+/// let user = ensure_root()?;
+/// {
+///     let _as_user = switch_to_user(&user)?;
+///     std::fs::write(user.home_path().join(".config/mytool/state"), b"done")?;
+/// }
+/// // root again
+/// ```
+pub fn switch_to_user(user: &InvokingUser) -> io::Result<UserSwitch> {
+    let mut switch = UserSwitch {
+        euid: unistd::geteuid(),
+        egid: unistd::getegid(),
+        groups: unistd::getgroups()?,
+        restored: false,
+    };
+    // groups and gid first, while still root
+    let result = unistd::setgroups(&user.nix_groups())
+        .and_then(|_| unistd::setegid(user.nix_gid()))
+        .and_then(|_| unistd::seteuid(user.nix_uid()));
+    if let Err(e) = result {
+        switch.restore_ids()?;
+        return Err(e.into());
+    }
+    Ok(switch)
+}
+
+/// Builds a `Command` that runs `program` entirely as `user`: real,
+/// effective and saved ids, and supplementary groups. `HOME`, `USER` and
+/// `LOGNAME` are set for that user, which is enough for tools such as git
+/// and ssh to find their configuration. Example:
+///
+/// ```
+/// let user = ensure_root()?;
+/// let status = user_command(&user, "git")?
+///     .args(["pull", "--ff-only"])
+///     .current_dir(user.home_path().join("src/project"))
+///     .status()?;
+/// ```
+pub fn user_command(user: &InvokingUser, program: impl AsRef<OsStr>) -> io::Result<Command> {
+    let (uid, gid) = (user.uid(), user.gid());
+    let groups = user.groups().to_vec();
+    let mut cmd = Command::new(program);
+    cmd.env("HOME", user.home_path())
+        .env("USER", user.name())
+        .env("LOGNAME", user.name());
+    // std applies its own uid and gid before pre_exec hooks run, which
+    // would leave no permission to set the groups, so do all three here
+    unsafe {
+        cmd.pre_exec(move || {
+            // only async-signal-safe calls are allowed after fork
+            if libc::setgroups(groups.len(), groups.as_ptr()) != 0
+                || libc::setresgid(gid, gid, gid) != 0
+                || libc::setresuid(uid, uid, uid) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(cmd)
+}
+
+/// Options for permanently dropping root privileges. See
+/// [`drop_privileges()`] for the defaults. Example:
+///
+/// ```
+/// let user = ensure_root()?;
+/// // root-only setup, such as binding a low port
+/// PrivilegeDrop::new().no_new_privs().set_env().drop_to(&user)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PrivilegeDrop {
+    no_new_privs: bool,
+    set_env: bool,
+    keep: CapSet,
+}
+impl PrivilegeDrop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Also sets `PR_SET_NO_NEW_PRIVS`, so that neither this process nor
+    /// its children can gain privileges again, even through setuid
+    /// executables such as sudo.
+    pub fn no_new_privs(&mut self) -> &mut Self {
+        self.no_new_privs = true;
+        self
+    }
+    /// Also sets `HOME`, `USER` and `LOGNAME` in the environment of this
+    /// process.
+    pub fn set_env(&mut self) -> &mut Self {
+        self.set_env = true;
+        self
+    }
+    /// Keeps `caps` after the drop, in the effective, permitted,
+    /// inheritable and ambient sets, so that both this process and the
+    /// programs it runs have them. `CAP_SETUID` and `CAP_SETGID` cannot be
+    /// kept, since they allow becoming root again.
+    ///
+    /// Capabilities belong to threads, while the uid change applies to
+    /// every thread, so that any other thread would be left with none.
+    /// [`Self::drop_to()`] therefore refuses to keep capabilities once
+    /// the process has started a second thread, such as that of an async
+    /// runtime; drop privileges before starting any. Example:
+    ///
+    /// ```
+    /// PrivilegeDrop::new()
+    ///     .keep_capabilities(Capability::NET_BIND_SERVICE.into())
+    ///     .drop_to(&user)?;
+    /// let listener = std::net::TcpListener::bind("0.0.0.0:80")?;
+    /// ```
+    pub fn keep_capabilities(&mut self, caps: CapSet) -> &mut Self {
+        self.keep = self.keep | caps;
+        self
+    }
+    /// Drops to `user` for the rest of the life of the process. If an
+    /// error is returned, the process may have been left with a mix of
+    /// ids, and should exit.
+    pub fn drop_to(&self, user: &InvokingUser) -> io::Result<()> {
+        if user.uid() == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot drop privileges to root",
+            ));
+        }
+        if self.keep.contains(Capability::SETUID) || self.keep.contains(Capability::SETGID) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "keeping CAP_SETUID or CAP_SETGID would allow regaining root",
+            ));
+        }
+        let keep = !self.keep.is_empty();
+        if keep && caps::thread_count()? > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "capabilities can only be kept while the process has a single thread",
+            ));
+        }
+        let (uid, gid, groups) = (user.nix_uid(), user.nix_gid(), user.nix_groups());
+        // groups and gids have to go while still root
+        unistd::setgroups(&groups)?;
+        unistd::setresgid(gid, gid, gid)?;
+        if keep {
+            caps::set_keep_caps(true)?;
+        }
+        unistd::setresuid(uid, uid, uid)?;
+        if keep {
+            caps::retain(self.keep)?;
+            caps::set_keep_caps(false)?;
+        }
+        verify_dropped(uid, gid, &groups, self.keep)?;
+        if self.no_new_privs {
+            let r = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+            if r != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if self.set_env {
+            std::env::set_var("HOME", user.home_path());
+            std::env::set_var("USER", user.name());
+            std::env::set_var("LOGNAME", user.name());
+        }
+        Ok(())
+    }
+}
+
+/// Permanently drops root privileges to `user`, usually the one returned
+/// by [`crate::ensure_root()`]: sets the supplementary groups, then the
+/// real, effective and saved gid, then the same for the uid, and verifies
+/// that root cannot be regained. Use [`PrivilegeDrop`] for more options.
+pub fn drop_privileges(user: &InvokingUser) -> io::Result<()> {
+    PrivilegeDrop::new().drop_to(user)
+}
+
+/// Checks that all ids are those of the user, that no capabilities but
+/// `keep` are left, and that root cannot be regained.
+fn verify_dropped(uid: Uid, gid: Gid, groups: &[Gid], keep: CapSet) -> io::Result<()> {
+    let failed = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("privileges were not dropped: {what}"),
+        )
+    };
+    let ids = unistd::getresuid()?;
+    if [ids.real, ids.effective, ids.saved] != [uid; 3] {
+        return Err(failed("uid still differs"));
+    }
+    let ids = unistd::getresgid()?;
+    if [ids.real, ids.effective, ids.saved] != [gid; 3] {
+        return Err(failed("gid still differs"));
+    }
+    let mut expected: Vec<_> = groups.iter().map(|g| g.as_raw()).collect();
+    let mut actual: Vec<_> = unistd::getgroups()?.iter().map(|g| g.as_raw()).collect();
+    expected.sort();
+    expected.dedup();
+    actual.sort();
+    actual.dedup();
+    if actual != expected {
+        return Err(failed("supplementary groups still differ"));
+    }
+    if Capabilities::current()?.permitted != keep {
+        return Err(failed("capabilities still differ"));
+    }
+    if unistd::setuid(Uid::from_raw(0)).is_ok() || unistd::seteuid(Uid::from_raw(0)).is_ok() {
+        return Err(failed("root uid could be regained"));
+    }
+    if gid.as_raw() != 0 && unistd::setegid(Gid::from_raw(0)).is_ok() {
+        return Err(failed("root gid could be regained"));
+    }
+    Ok(())
+}
+
+/// Runs `f` in a forked child, for tests that change the ids or the
+/// capabilities of the process, and returns what it returned, with the
+/// error as its message.
+#[cfg(test)]
+pub(crate) fn in_child(f: impl FnOnce() -> io::Result<()>) -> Result<(), String> {
+    use nix::{
+        sys::wait::{waitpid, WaitStatus},
+        unistd::{fork, pipe, ForkResult},
+    };
+    use std::{fs::File, io::Read, io::Write};
+    let (r, w) = pipe().expect("Should create a pipe");
+    match unsafe { fork() }.expect("Should fork") {
+        ForkResult::Child => {
+            drop(r);
+            // a failed assertion must not unwind into the test harness
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+                .unwrap_or_else(|_| Err(io::Error::other("the child panicked")));
+            let code = match result {
+                Ok(()) => 0,
+                Err(e) => {
+                    _ = File::from(w).write_all(e.to_string().as_bytes());
+                    1
+                }
+            };
+            unsafe { libc::_exit(code) };
+        }
+        ForkResult::Parent { child } => {
+            drop(w);
+            let mut message = String::new();
+            File::from(r)
+                .read_to_string(&mut message)
+                .expect("Should read the result");
+            match waitpid(child, None).expect("Should wait for the child") {
+                WaitStatus::Exited(_, 0) => Ok(()),
+                WaitStatus::Exited(..) => Err(message),
+                status => Err(format!("{status:?}")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nobody() -> InvokingUser {
+        InvokingUser::from_name("nobody").unwrap()
+    }
+
+    #[test]
+    fn command_as_user() {
+        let user = nobody();
+        let output = user_command(&user, "/bin/sh")
+            .unwrap()
+            .args(["-c", "id -u; id -g; id -G; echo $HOME $USER"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let groups: Vec<String> = user.groups().iter().map(u32::to_string).collect();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!(
+                "{}\n{}\n{}\n{} nobody\n",
+                user.uid(),
+                user.gid(),
+                groups.join(" "),
+                user.home_path().display()
+            )
+        );
+        // this process stays root
+        assert!(unistd::getuid().is_root());
+    }
+
+    #[test]
+    fn drop_in_child() {
+        let user = nobody();
+        in_child(|| {
+            drop_privileges(&user)?;
+            let ids = unistd::getresuid()?;
+            assert_eq!([ids.real, ids.effective, ids.saved], [user.nix_uid(); 3]);
+            assert!(Capabilities::current()?.effective.is_empty());
+            Ok(())
+        })
+        .unwrap();
+        // keeping a capability, and no_new_privs
+        in_child(|| {
+            PrivilegeDrop::new()
+                .no_new_privs()
+                .set_env()
+                .keep_capabilities(Capability::NET_BIND_SERVICE.into())
+                .drop_to(&user)?;
+            let caps = Capabilities::current()?;
+            assert_eq!(caps.effective, Capability::NET_BIND_SERVICE.into());
+            assert_eq!(std::env::var("USER").as_deref(), Ok("nobody"));
+            assert_eq!(
+                unsafe { libc::prctl(libc::PR_GET_NO_NEW_PRIVS, 0, 0, 0, 0) },
+                1
+            );
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn refused_drops() {
+        let root = InvokingUser::from_uid(0).unwrap();
+        let e = drop_privileges(&root).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        for cap in [Capability::SETUID, Capability::SETGID] {
+            let e = PrivilegeDrop::new()
+                .keep_capabilities(cap.into())
+                .drop_to(&nobody())
+                .unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+        // nothing was changed
+        assert!(unistd::geteuid().is_root());
+    }
+
+    #[test]
+    fn keeping_capabilities_with_threads() {
+        let user = nobody();
+        let result = in_child(|| {
+            let (tx, rx) = std::sync::mpsc::channel::<()>();
+            let thread = std::thread::spawn(move || rx.recv());
+            let result = PrivilegeDrop::new()
+                .keep_capabilities(Capability::NET_BIND_SERVICE.into())
+                .drop_to(&user);
+            drop(tx);
+            _ = thread.join();
+            result
+        });
+        assert_eq!(
+            result.unwrap_err(),
+            "capabilities can only be kept while the process has a single thread"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! A small, runtime-agnostic reactor for awaiting terminal input, timers
+//! and child processes. Enabled by the `async` cargo feature.
+//!
+//! A background thread waits on an epoll fd and wakes the tasks that are
+//! interested in each ready fd. Nothing here depends on a particular
+//! executor: the futures may be polled by tokio, smol, or the bundled
+//! [`block_on()`].
+//!
+//! Example, waiting for a child process while reacting to keystrokes:
+//!
+//! ```
+//! use std::{future::poll_fn, io::{stdin, stdout}, process::Command, task::Poll};
+//! let mut t = Term::new(stdin(), stdout())?;
+//! t.raw_mode().set(SetAction::TCSAFLUSH)?;
+//! let mut events = EventStream::new(t)?;
+//! let mut child = Command::new("sleep").arg("5").spawn()?;
+//! let mut exit = ChildExit::new(&child)?;
+//! block_on(poll_fn(|cx| {
+//!     if let Poll::Ready(r) = exit.poll_exit(cx, &mut child) {
+//!         return Poll::Ready(r.map(|_| ()));
+//!     }
+//!     while let Poll::Ready(Some(ev)) = events.poll_next(cx) {
+//!         if let TermEvent::Key(Key::Char('q')) = ev? {
+//!             _ = child.kill();
+//!         }
+//!     }
+//!     Poll::Pending
+//! }))?;
+//! ```
+use crate::epoll::{Epoll, TimerFd};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    pin::{pin, Pin},
+    process::{Child, ExitStatus},
+    sync::{Arc, Mutex, OnceLock},
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::Duration,
+};
+
+struct Reactor {
+    epoll: Epoll,
+    wakers: Mutex<HashMap<RawFd, Waker>>,
+}
+impl Reactor {
+    fn get() -> io::Result<&'static Reactor> {
+        static REACTOR: OnceLock<Reactor> = OnceLock::new();
+        if let Some(r) = REACTOR.get() {
+            return Ok(r);
+        }
+        let epoll = Epoll::new()?;
+        let reactor = REACTOR.get_or_init(|| Reactor {
+            epoll,
+            wakers: Mutex::new(HashMap::new()),
+        });
+        static STARTED: OnceLock<()> = OnceLock::new();
+        STARTED.get_or_init(|| {
+            thread::Builder::new()
+                .name("scripting-reactor".into())
+                .spawn(move || reactor.run())
+                .expect("Should have spawned the reactor thread");
+        });
+        Ok(reactor)
+    }
+    fn run(&self) {
+        let mut tokens = Vec::new();
+        loop {
+            if self.epoll.wait(&mut tokens, None).is_err() {
+                // nothing sensible to do but try again
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            let mut wakers = self.wakers.lock().unwrap();
+            for token in tokens.iter() {
+                if let Some(w) = wakers.remove(&(*token as RawFd)) {
+                    w.wake();
+                }
+            }
+        }
+    }
+    /// Arranges for the task to be woken once `fd` is readable.
+    fn wake_when_readable(&self, fd: RawFd, waker: &Waker) -> io::Result<()> {
+        self.wakers.lock().unwrap().insert(fd, waker.clone());
+        self.epoll.watch_readable(fd, fd as u64, true)
+    }
+    fn forget(&self, fd: RawFd) {
+        self.wakers.lock().unwrap().remove(&fd);
+        _ = self.epoll.unwatch(fd);
+    }
+}
+
+/// Polls for readability of `fd`. Returns `Ready` if the fd can be read
+/// without blocking; otherwise registers the task's waker with the
+/// reactor.
+pub(crate) fn poll_readable(fd: RawFd, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    match crate::epoll::is_readable(fd) {
+        Ok(true) => Poll::Ready(Ok(())),
+        Ok(false) => match Reactor::get().and_then(|r| r.wake_when_readable(fd, cx.waker())) {
+            // the fd may have become readable before it was registered
+            Ok(()) => match crate::epoll::is_readable(fd) {
+                Ok(true) => Poll::Ready(Ok(())),
+                Ok(false) => Poll::Pending,
+                Err(e) => Poll::Ready(Err(e)),
+            },
+            Err(e) => Poll::Ready(Err(e)),
+        },
+        Err(e) => Poll::Ready(Err(e)),
+    }
+}
+
+/// Removes any interest in `fd` from the reactor. Must be called before
+/// the fd is closed.
+pub(crate) fn forget(fd: RawFd) {
+    if let Ok(r) = Reactor::get() {
+        r.forget(fd);
+    }
+}
+
+/// A [`TimerFd`] registered with the reactor. Created by [`sleep()`] or
+/// [`Interval::new()`].
+struct Timer {
+    fd: TimerFd,
+}
+impl Timer {
+    fn new(first: Duration, interval: Duration) -> io::Result<Self> {
+        Ok(Self {
+            fd: TimerFd::new(first, interval)?,
+        })
+    }
+    /// Returns the number of expirations since the last poll.
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        loop {
+            if let Err(e) = std::task::ready!(poll_readable(self.fd.as_raw_fd(), cx)) {
+                return Poll::Ready(Err(e));
+            }
+            match self.fd.read() {
+                Ok(Some(count)) => return Poll::Ready(Ok(count)),
+                Ok(None) => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+impl Drop for Timer {
+    fn drop(&mut self) {
+        forget(self.fd.as_raw_fd());
+    }
+}
+
+/// Future returned by [`sleep()`].
+pub struct Sleep {
+    timer: io::Result<Timer>,
+}
+impl Future for Sleep {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.timer.as_mut() {
+            Ok(t) => t.poll_expired(cx).map_ok(|_| ()),
+            Err(e) => Poll::Ready(Err(io::Error::new(e.kind(), e.to_string()))),
+        }
+    }
+}
+
+/// Completes after `duration` has elapsed.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        timer: Timer::new(duration, Duration::ZERO),
+    }
+}
+
+/// A periodic timer, useful for driving spinners and progress displays.
+pub struct Interval {
+    timer: Timer,
+}
+impl Interval {
+    /// Creates a timer that first ticks after `period`, then every `period`.
+    pub fn new(period: Duration) -> io::Result<Self> {
+        Ok(Self {
+            timer: Timer::new(period, period.max(Duration::from_nanos(1)))?,
+        })
+    }
+    /// Polls for the next tick. Resolves to the number of ticks that
+    /// elapsed since the last poll, which is more than one if the task
+    /// fell behind.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        self.timer.poll_expired(cx)
+    }
+    /// Waits for the next tick.
+    pub async fn tick(&mut self) -> io::Result<u64> {
+        std::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+}
+
+/// Awaits the exit of a child process via a pidfd (Linux 5.3 and later).
+pub struct ChildExit {
+    pidfd: OwnedFd,
+}
+impl ChildExit {
+    pub fn new(child: &Child) -> io::Result<Self> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, child.id() as libc::pid_t, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            pidfd: unsafe { OwnedFd::from_raw_fd(fd as RawFd) },
+        })
+    }
+    /// Polls for the exit of `child`, which must be the process this was
+    /// created for. The child is reaped when it exits.
+    pub fn poll_exit(
+        &mut self,
+        cx: &mut Context<'_>,
+        child: &mut Child,
+    ) -> Poll<io::Result<ExitStatus>> {
+        loop {
+            if let Err(e) = std::task::ready!(poll_readable(self.pidfd.as_raw_fd(), cx)) {
+                return Poll::Ready(Err(e));
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => return Poll::Ready(Ok(status)),
+                // readable pidfds always have an exit status
+                Ok(None) => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+impl Drop for ChildExit {
+    fn drop(&mut self) {
+        forget(self.pidfd.as_raw_fd());
+    }
+}
+
+/// Waits for a child process to exit without blocking the executor.
+pub async fn wait_child(child: &mut Child) -> io::Result<ExitStatus> {
+    let mut exit = ChildExit::new(child)?;
+    std::future::poll_fn(|cx| exit.poll_exit(cx, child)).await
+}
+
+struct ThreadWaker(Thread);
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future to completion on the current thread. This is enough for
+/// scripts that do not otherwise use an async runtime.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+            return out;
+        }
+        thread::park();
+    }
+}
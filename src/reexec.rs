@@ -0,0 +1,275 @@
+//! Replacing the current process with a new run of the same program,
+//! optionally through a wrapper such as nice or an escalator.
+use crate::Escalator;
+use std::{
+    env,
+    ffi::{CString, OsStr, OsString},
+    fs::File,
+    io,
+    os::{
+        fd::AsRawFd,
+        unix::{
+            ffi::{OsStrExt, OsStringExt},
+            fs::MetadataExt,
+            process::CommandExt,
+        },
+    },
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Re-runs the current program. By default the arguments, including
+/// `argv[0]`, are exactly those the process was started with, and the
+/// binary is executed through `/proc/self/exe`, so the same program runs
+/// even if the file was replaced or deleted since, as during an upgrade.
+/// Example:
+///
+/// ```
+/// // continue at a lower priority
+/// if std::env::var_os("MYTOOL_NICED").is_none() {
+///     std::env::set_var("MYTOOL_NICED", "1");
+///     let e = Reexec::new().wrapper("nice", ["-n", "10"]).exec();
+///     eprintln!("cannot re-run through nice: {e}");
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Reexec {
+    wrapper: Vec<OsString>,
+    argv0: Option<OsString>,
+    args: Option<Vec<OsString>>,
+}
+impl Reexec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Runs the program through `program`, with `args` before the path of
+    /// the program, such as `nice -n 10`, `ionice -c 3` or `unshare -n`.
+    /// Calling this again adds a wrapper that the previous one runs. The
+    /// wrapper chooses `argv[0]` for the program, so [`Self::argv0()`]
+    /// has no effect.
+    pub fn wrapper<S: AsRef<OsStr>>(
+        &mut self,
+        program: impl AsRef<OsStr>,
+        args: impl IntoIterator<Item = S>,
+    ) -> &mut Self {
+        self.wrapper.push(program.as_ref().to_os_string());
+        self.wrapper
+            .extend(args.into_iter().map(|a| a.as_ref().to_os_string()));
+        self
+    }
+    /// Replaces `argv[0]`.
+    pub fn argv0(&mut self, argv0: impl AsRef<OsStr>) -> &mut Self {
+        self.argv0 = Some(argv0.as_ref().to_os_string());
+        self
+    }
+    /// Replaces the arguments after `argv[0]`.
+    pub fn args<S: AsRef<OsStr>>(&mut self, args: impl IntoIterator<Item = S>) -> &mut Self {
+        self.args = Some(
+            args.into_iter()
+                .map(|a| a.as_ref().to_os_string())
+                .collect(),
+        );
+        self
+    }
+    /// `argv[0]` and the arguments. Fails with an error of kind
+    /// `InvalidInput` if any of them contains a nul byte, since it could
+    /// not be passed on whole.
+    fn argv(&self) -> io::Result<Vec<OsString>> {
+        let mut current = env::args_os();
+        let argv0 = current.next();
+        let argv0 = self.argv0.clone().or(argv0).unwrap_or_default();
+        let args = self.args.clone().unwrap_or_else(|| current.collect());
+        let argv: Vec<OsString> = [argv0].into_iter().chain(args).collect();
+        check_nul(&argv)?;
+        Ok(argv)
+    }
+    /// Returns a path that runs this program, and the arguments after
+    /// `argv[0]`, for passing to another program. If the binary was
+    /// replaced or deleted, the path is a `/proc/self/fd` entry for the
+    /// returned file, which is left open across exec and has to be kept
+    /// until then. Escalators close inherited descriptors, so with
+    /// `escalating` this fails with an error of kind `NotFound` instead.
+    pub(crate) fn program(
+        &self,
+        escalating: bool,
+    ) -> io::Result<(PathBuf, Vec<OsString>, Option<File>)> {
+        let mut argv = self.argv()?;
+        argv.remove(0);
+        check_nul(&self.wrapper)?;
+        let running = File::open("/proc/self/exe")?;
+        let meta = running.metadata()?;
+        // the link reads "/path (deleted)" once the file is gone
+        let path = env::current_exe()?;
+        let same = path
+            .metadata()
+            .is_ok_and(|m| m.dev() == meta.dev() && m.ino() == meta.ino());
+        if same {
+            return Ok((path, argv, None));
+        }
+        if escalating {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} was replaced or deleted since this program started",
+                    path.to_string_lossy().trim_end_matches(" (deleted)")
+                ),
+            ));
+        }
+        let fd = running.as_raw_fd();
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((
+            PathBuf::from(format!("/proc/self/fd/{fd}")),
+            argv,
+            Some(running),
+        ))
+    }
+    /// Replaces the current process, and only returns if that fails.
+    pub fn exec(&self) -> io::Error {
+        let escalating = self.wrapper.iter().any(|word| {
+            Escalator::ALL
+                .iter()
+                .any(|e| Path::new(word).file_name() == Some(e.program().as_ref()))
+        });
+        match self.wrapper.split_first() {
+            // `_running` keeps the descriptor that `path` may refer to
+            // open, and closes it if exec fails
+            Some((program, args)) => match self.program(escalating) {
+                Ok((path, argv, _running)) => {
+                    Command::new(program).args(args).arg(path).args(argv).exec()
+                }
+                Err(e) => e,
+            },
+            None => self.exec_self().unwrap_err(),
+        }
+    }
+    fn exec_self(&self) -> io::Result<()> {
+        let argv = to_cstrings(self.argv()?)?;
+        let envp = to_cstrings(env::vars_os().map(|(name, value)| {
+            let mut var = name;
+            var.push("=");
+            var.push(value);
+            var
+        }))?;
+        let exe = File::open("/proc/self/exe")?;
+        let argv_ptrs = null_terminated(&argv);
+        let envp_ptrs = null_terminated(&envp);
+        unsafe { libc::fexecve(exe.as_raw_fd(), argv_ptrs.as_ptr(), envp_ptrs.as_ptr()) };
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Replaces the current process with a new run of the same program, with
+/// the same arguments. See [`Reexec`] for options. Only returns if that
+/// fails.
+pub fn reexec_self() -> io::Error {
+    Reexec::new().exec()
+}
+
+fn check_nul(args: &[OsString]) -> io::Result<()> {
+    match args.iter().find(|a| a.as_bytes().contains(&0)) {
+        Some(a) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("argument {a:?} contains a nul byte"),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn to_cstrings(strings: impl IntoIterator<Item = OsString>) -> io::Result<Vec<CString>> {
+    strings
+        .into_iter()
+        .map(|s| CString::new(s.into_vec()).map_err(io::Error::from))
+        .collect()
+}
+
+fn null_terminated(strings: &[CString]) -> Vec<*const libc::c_char> {
+    strings
+        .iter()
+        .map(|s| s.as_ptr())
+        .chain([std::ptr::null()])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Set for the copy of the test binary run by `replaced_binary`.
+    const REPLACED_VAR: &str = "REEXEC_TEST_REPLACED";
+
+    #[test]
+    fn argv() {
+        let current: Vec<OsString> = env::args_os().collect();
+        assert_eq!(Reexec::new().argv().unwrap(), current);
+        let argv = Reexec::new().argv0("renamed").argv().unwrap();
+        assert_eq!(argv[0], "renamed");
+        assert_eq!(argv[1..], current[1..]);
+        let argv = Reexec::new()
+            .args(["a", "", "--b c"])
+            .argv0("x")
+            .argv()
+            .unwrap();
+        assert_eq!(argv, ["x", "a", "", "--b c"]);
+    }
+
+    #[test]
+    fn nul_bytes() {
+        let e = Reexec::new().args(["a\0b"]).argv().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        let e = Reexec::new().argv0("a\0").argv().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        let e = Reexec::new()
+            .wrapper("nice", ["-n\0"])
+            .program(false)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        // exec reports it instead of running anything
+        let e = Reexec::new().args(["a\0b"]).exec();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn program() {
+        let (path, args, running) = Reexec::new().args(["x"]).program(true).unwrap();
+        assert_eq!(path, env::current_exe().unwrap());
+        assert_eq!(args, ["x"]);
+        assert!(running.is_none());
+    }
+
+    /// Runs a copy of the test binary, which deletes itself and checks
+    /// what `program()` makes of that.
+    #[test]
+    fn replaced_binary() {
+        if env::var_os(REPLACED_VAR).is_some() {
+            std::fs::remove_file(env::current_exe().unwrap()).unwrap();
+            let e = Reexec::new().program(true).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::NotFound);
+            assert!(!e.to_string().contains("(deleted)"), "{e}");
+            let (path, _, running) = Reexec::new().program(false).unwrap();
+            let fd = running.expect("Should keep the binary open").as_raw_fd();
+            assert_eq!(path, Path::new(&format!("/proc/self/fd/{fd}")));
+            return;
+        }
+        let copy = env::temp_dir().join(format!("reexec-test-{}", std::process::id()));
+        std::fs::copy(env::current_exe().unwrap(), &copy).unwrap();
+        // another test forking while the copy was open for writing keeps
+        // it busy until that child execs
+        let status = loop {
+            match Command::new(&copy)
+                .args(["reexec::tests::replaced_binary", "--exact"])
+                .env(REPLACED_VAR, "1")
+                .stdout(std::process::Stdio::null())
+                .status()
+            {
+                Err(e) if e.raw_os_error() == Some(libc::ETXTBSY) => {
+                    std::thread::sleep(std::time::Duration::from_millis(10))
+                }
+                result => break result.unwrap(),
+            }
+        };
+        assert!(status.success());
+        assert!(!copy.exists());
+    }
+}
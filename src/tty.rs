@@ -14,7 +14,13 @@ use libc::OPOST;
 // misc flags (lflag)
 use libc::{ECHO, ECHONL, ICANON, IEXTEN, ISIG};
 // exports
+pub mod flags;
+pub mod keys;
 pub mod password;
+pub mod serial;
+pub mod settings;
+#[cfg(feature = "async")]
+pub mod stream;
 use password::*;
 
 /// Specifies behavior of [`libc::tcsetattr`]. Used in this library by [`Term::set()`] and [`Term::reset()`].
@@ -142,10 +148,23 @@ impl<I, O: AsRawFd> Term<I, O> {
             t: (t.clone(), t),
         })
     }
+    /// Returns a reference to the input.
+    pub fn input(&self) -> &I {
+        &self.fd_in
+    }
+    /// Returns a reference to the output.
+    pub fn output(&self) -> &O {
+        &self.fd_out
+    }
     /// Returns false if the output is not connected to a terminal.
     pub fn is_a_tty(&self) -> bool {
         isatty(self.fd_out.as_raw_fd())
     }
+    /// Returns the size of the terminal connected to the output as
+    /// `(cols, rows)`.
+    pub fn size(&self) -> io::Result<(u16, u16)> {
+        get_winsize(self.fd_out.as_raw_fd())
+    }
     /// Attempts to save the settings from the terminal currently connected
     /// to the output. Future invocations of [`Self::reset()`] will use
     /// this state. 
@@ -154,11 +173,50 @@ impl<I, O: AsRawFd> Term<I, O> {
         self.t = (t.clone(), t);
         Ok(())
     }
+    /// Returns the settings that [`Self::reset()`] restores.
+    pub fn original_termios(&self) -> settings::Termios {
+        self.t.0.into()
+    }
+    /// Returns the settings that [`Self::set()`] applies, including any
+    /// changes made since the last `set()`.
+    pub fn working_termios(&self) -> settings::Termios {
+        self.t.1.into()
+    }
+    /// Queries the terminal for its current settings, which may differ from
+    /// both of the above if another program changed them.
+    pub fn current_termios(&self) -> io::Result<settings::Termios> {
+        get_termios(self.fd_out.as_raw_fd()).map(Into::into)
+    }
     /// Gives the provided fn direct access to the [`libc::termios`]
     /// struct.
     pub fn with_termios(&mut self, mut f: impl FnOnce(&mut libc::termios)) {
         f(&mut self.t.1);
     }
+    /// Typed alternative to [`Self::with_termios()`]. The provided fn
+    /// describes changes on a [`flags::TermiosBuilder`], which are
+    /// validated and then applied to the working copy of the settings.
+    /// Nothing is changed if validation fails. Example:
+    ///
+    /// ```
+    /// let mut t = Term::new((), 1)?;
+    /// // enable ISIG but keep ICANON off
+    /// t.with_flags(|b| {
+    ///     b.enable(LocalFlags::ISIG)
+    ///         .disable(LocalFlags::ICANON | LocalFlags::ECHO)
+    ///         .control_char(ControlChar::Intr, Some(0x18)) // ctrl-x
+    ///         .read_min(1);
+    /// })?
+    /// .set(SetAction::TCSAFLUSH)?;
+    /// ```
+    pub fn with_flags(
+        &mut self,
+        f: impl FnOnce(&mut flags::TermiosBuilder),
+    ) -> io::Result<&mut Self> {
+        let mut builder = flags::TermiosBuilder::new();
+        f(&mut builder);
+        self.t.1 = builder.build(self.t.1.into())?.into();
+        Ok(self)
+    }
     /// Raw mode: unsets ECHO and ICANON, disables output flow control,
     /// disables ctrl-v, disables input carriage return translation
     /// (ctrl-m), disables ctrl-c signalling, and some other stuff. Note
@@ -280,6 +338,20 @@ impl<I, O: AsRawFd> Term<I, O> {
         self.set(action)
     }
 }
+impl<I, O: Write> Term<I, O> {
+    /// Asks the terminal to surround pasted text with markers, so that it
+    /// is reported as a single [`keys::TermEvent::Paste`] rather than as
+    /// keystrokes. Remember to disable it before exiting.
+    pub fn enable_bracketed_paste(&mut self) -> io::Result<()> {
+        self.write_all(b"\x1b[?2004h")?;
+        self.flush()
+    }
+    /// Turns off bracketed paste.
+    pub fn disable_bracketed_paste(&mut self) -> io::Result<()> {
+        self.write_all(b"\x1b[?2004l")?;
+        self.flush()
+    }
+}
 impl<I: Read, O: AsRawFd + Write> Term<I, O> {
     /// Convenience function that sets the terminal to password mode,
     /// prompts for a password, and resets the terminal. A `": "` sequence is
@@ -293,13 +365,147 @@ impl<I: Read, O: AsRawFd + Write> Term<I, O> {
     /// println!("Password entered was {:?}", pw.as_str());
     /// ```
     pub fn prompt_for_password(&mut self, prompt: impl std::fmt::Display) -> io::Result<Password> {
+        let mut pw = Password::try_new()?;
+        self.prompt_for_password_into(prompt, &mut pw)?;
+        Ok(pw)
+    }
+    /// Like [`Self::prompt_for_password()`], but reads into an existing
+    /// [`Password`] of any buffer size. Input that does not fit is read
+    /// and discarded, and [`Password::is_truncated()`] reports it.
+    pub fn prompt_for_password_into<const N: usize>(
+        &mut self,
+        prompt: impl std::fmt::Display,
+        pw: &mut Password<N>,
+    ) -> io::Result<()> {
         self.password_mode().set(SetAction::TCSAFLUSH)?;
-        let mut pw = Password::new();
         write!(self, "{}: ", prompt)?;
         self.fd_out.flush()?;
-        pw.read_line(&mut self.fd_in)?;
+        let result = pw.read_line(&mut self.fd_in);
         self.reset(SetAction::TCSAFLUSH)?;
-        Ok(pw)
+        result
+    }
+    /// Like [`Self::prompt_for_password()`], but uses raw mode and echoes
+    /// `mask` for every character typed, so the user can see how much
+    /// they entered. With a `mask` of `None`, nothing is echoed. Backspace
+    /// removes the last character and ctrl-u clears the input. Ctrl-c
+    /// returns an error of kind `Interrupted`, and ctrl-d ends the input
+    /// like enter does. Input is decoded into keys, so other keys such as
+    /// arrows and ESC are ignored whole, and a multibyte character is
+    /// masked once. It is decoded where it was read, in a buffer on the
+    /// stack that is zeroed before returning. Input that does not fit in the buffer is
+    /// discarded, and [`Password::is_truncated()`] reports it. Example:
+    ///
+    /// ```
+    /// use std::io::{stdin, stdout};
+    /// let mut t = Term::new(stdin(), stdout())?;
+    /// let pw = t.prompt_for_password_masked("Enter the password", Some('*'))?;
+    /// ```
+    pub fn prompt_for_password_masked(
+        &mut self,
+        prompt: impl std::fmt::Display,
+        mask: Option<char>,
+    ) -> io::Result<Password> {
+        let mut pw = Password::try_new()?;
+        self.raw_mode().set(SetAction::TCSAFLUSH)?;
+        write!(self, "{}: ", prompt)?;
+        self.fd_out.flush()?;
+        let result = self.read_masked(&mut pw, mask);
+        // raw mode disables output processing, so write the carriage return
+        _ = write!(self, "\r\n");
+        _ = self.fd_out.flush();
+        self.reset(SetAction::TCSAFLUSH)?;
+        result.map(|_| pw)
+    }
+    fn read_masked<const N: usize>(
+        &mut self,
+        pw: &mut Password<N>,
+        mask: Option<char>,
+    ) -> io::Result<()> {
+        let mut buf = [0u8; 64];
+        let result = self.read_masked_keys(pw, mask, &mut buf);
+        buf.fill(0);
+        result
+    }
+    fn read_masked_keys<const N: usize>(
+        &mut self,
+        pw: &mut Password<N>,
+        mask: Option<char>,
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        use keys::Key;
+        let mut in_paste = false;
+        // the bytes of an incomplete key, kept at the start of `buf`
+        let mut pending = 0;
+        loop {
+            if pending == buf.len() {
+                // no key is this long, so drop it
+                pending = 0;
+            }
+            // terminals send an escape sequence in one write, so reading
+            // whatever is available tells a lone ESC apart
+            let n = self.fd_in.read(&mut buf[pending..])?;
+            if n == 0 {
+                return Ok(());
+            }
+            let end = pending + n;
+            let mut at = 0;
+            while let Some((key, len)) = keys::decode_in_place(&buf[at..end], &mut in_paste) {
+                at += len;
+                match key {
+                    // pasted text is taken as it is, without editing
+                    Some(Key::Char(c)) => self.push_masked(pw, mask, c)?,
+                    _ if in_paste => (),
+                    Some(Key::Enter | Key::Ctrl('d')) => return Ok(()),
+                    Some(Key::Ctrl('c')) => return Err(io::ErrorKind::Interrupted.into()),
+                    Some(Key::Backspace) => {
+                        let erased = pw.pop_char() as usize;
+                        self.erase_masks(mask, erased)?;
+                    }
+                    Some(Key::Ctrl('u')) => {
+                        let erased = pw.char_count();
+                        pw.clear();
+                        self.erase_masks(mask, erased)?;
+                    }
+                    // bytes that are not UTF-8 may be part of the password
+                    Some(Key::Unknown(mut bytes)) => bytes.fill(0),
+                    // arrow keys, alt combinations, a lone ESC and so on
+                    _ => (),
+                }
+            }
+            buf.copy_within(at..end, 0);
+            pending = end - at;
+            buf[pending..end].fill(0);
+        }
+    }
+    /// Appends `c` to the password and echoes the mask once for it, or
+    /// marks the password truncated if it does not fit.
+    fn push_masked<const N: usize>(
+        &mut self,
+        pw: &mut Password<N>,
+        mask: Option<char>,
+        c: char,
+    ) -> io::Result<()> {
+        let len = pw.len();
+        let room = &mut pw.as_mut_slice()[len..];
+        if c.len_utf8() > room.len() {
+            pw.set_truncated();
+            return Ok(());
+        }
+        c.encode_utf8(room);
+        if let Some(mask) = mask {
+            write!(self, "{mask}")?;
+            self.fd_out.flush()?;
+        }
+        Ok(())
+    }
+    fn erase_masks(&mut self, mask: Option<char>, count: usize) -> io::Result<()> {
+        if mask.is_some() && count > 0 {
+            for _ in 0..count {
+                write!(self, "\x08 \x08")?;
+            }
+            self.fd_out.flush()?;
+        }
+        Ok(())
     }
 }
 
@@ -315,11 +521,24 @@ pub fn set_termios(fd: impl AsRawFd, action: SetAction, t: &termios) -> io::Resu
     io_result(unsafe { libc::tcsetattr(fd.as_raw_fd(), action.as_flag(), t) })
 }
 
+/// Returns the terminal size as `(cols, rows)` via the `TIOCGWINSZ` ioctl.
+pub fn get_winsize(fd: impl AsRawFd) -> io::Result<(u16, u16)> {
+    let mut ws = mem::MaybeUninit::<libc::winsize>::uninit();
+    io_result(unsafe { libc::ioctl(fd.as_raw_fd(), libc::TIOCGWINSZ, ws.as_mut_ptr()) })?;
+    let ws = unsafe { ws.assume_init() };
+    Ok((ws.ws_col, ws.ws_row))
+}
+
 /// Returns true if the fd is a tty
 pub fn isatty(fd: impl AsRawFd) -> bool {
     get_termios(fd).is_ok()
 }
 
+/// An `InvalidInput` error for settings that cannot be parsed or applied.
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
 /// Converts a c return value (c_int) to an io Result
 fn io_result(c_return: c_int) -> io::Result<()> {
     if c_return == 0 {
@@ -328,3 +547,72 @@ fn io_result(c_return: c_int) -> io::Result<()> {
         Err(io::Error::last_os_error())
     }
 }
+
+/// Opens a pseudo terminal pair, as `(master, slave)`, for tests.
+#[cfg(test)]
+pub(crate) fn openpty() -> io::Result<(std::fs::File, std::fs::File)> {
+    use std::os::fd::FromRawFd;
+    let (mut master, mut slave) = (0, 0);
+    let r = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    io_result(r)?;
+    unsafe {
+        Ok((
+            std::fs::File::from_raw_fd(master),
+            std::fs::File::from_raw_fd(slave),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn masked_input() {
+        let (mut master, slave) = openpty().unwrap();
+        let mut term = Term::new(slave.try_clone().unwrap(), slave).unwrap();
+        let typist = thread::spawn(move || {
+            let mut out = Vec::new();
+            let mut buf = [0u8; 64];
+            while !out.ends_with(b": ") {
+                let n = master.read(&mut buf).unwrap();
+                out.extend_from_slice(&buf[..n]);
+            }
+            // a lone ESC, then an arrow key, a character split across
+            // writes, and a backspace
+            for keys in [
+                &b"\x1b"[..],
+                b"a",
+                b"\x1b[D",
+                b"\xc3",
+                b"\xa9",
+                b"b\x7f",
+                // pasted, with the end marker split across writes
+                b"\x1b[200~x\ry\x1b[2",
+                b"01~",
+                b"\r",
+            ] {
+                master.write_all(keys).unwrap();
+                thread::sleep(Duration::from_millis(50));
+            }
+            while !out.ends_with(b"\r\n") {
+                let n = master.read(&mut buf).unwrap();
+                out.extend_from_slice(&buf[..n]);
+            }
+            out
+        });
+        let pw = term.prompt_for_password_masked("Pw", Some('*')).unwrap();
+        assert_eq!(pw.as_str(), Ok("a\u{e9}xy"));
+        assert!(!pw.is_truncated());
+        assert_eq!(typist.join().unwrap(), b"Pw: ***\x08 \x08**\r\n");
+    }
+}
@@ -0,0 +1,501 @@
+//! Typed termios flags, control characters and baud rates, as an
+//! alternative to twiddling bits in [`crate::Term::with_termios()`].
+use super::{
+    invalid,
+    settings::{baud_to_speed, FlagGroup, Termios},
+};
+use libc::{tcflag_t, termios};
+use std::{fmt, io, ops, time::Duration};
+
+/// Common interface of [`InputFlags`], [`OutputFlags`], [`ControlFlags`]
+/// and [`LocalFlags`].
+pub trait Flags: Copy {
+    /// The termios field the flags belong to.
+    const GROUP: FlagGroup;
+    fn bits(&self) -> tcflag_t;
+}
+
+macro_rules! flag_type {
+    (
+        $(#[$doc:meta])*
+        $name:ident, $group:expr, {
+            $($(#[$flag_doc:meta])* $flag:ident = $value:expr;)*
+        }
+    ) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+        pub struct $name(tcflag_t);
+        impl $name {
+            $($(#[$flag_doc])* pub const $flag: Self = Self($value);)*
+
+            /// No flags.
+            pub const fn empty() -> Self {
+                Self(0)
+            }
+            /// Returns true if all of `other` is contained in `self`.
+            pub const fn contains(&self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+        }
+        impl Flags for $name {
+            const GROUP: FlagGroup = $group;
+            fn bits(&self) -> tcflag_t {
+                self.0
+            }
+        }
+        impl ops::BitOr for $name {
+            type Output = Self;
+            fn bitor(self, rhs: Self) -> Self {
+                Self(self.0 | rhs.0)
+            }
+        }
+        impl ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, rhs: Self) {
+                self.0 |= rhs.0;
+            }
+        }
+        /// Lists the `stty` names of the flags.
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let names = <Self as Flags>::GROUP
+                    .flags()
+                    .iter()
+                    .filter(|(_, bits)| self.0 & bits == *bits)
+                    .map(|(name, _)| *name);
+                f.debug_tuple(stringify!($name))
+                    .field(&names.collect::<Vec<_>>())
+                    .finish()
+            }
+        }
+    };
+}
+
+flag_type!(
+    /// Input flags (`c_iflag`).
+    InputFlags, FlagGroup::Input, {
+        /// Ignore break conditions
+        IGNBRK = libc::IGNBRK;
+        /// Send SIGINT on break
+        BRKINT = libc::BRKINT;
+        /// Ignore characters with parity errors
+        IGNPAR = libc::IGNPAR;
+        /// Mark parity errors in the input
+        PARMRK = libc::PARMRK;
+        /// Enable input parity checking
+        INPCK = libc::INPCK;
+        /// Strip the eighth bit
+        ISTRIP = libc::ISTRIP;
+        /// Translate newline to carriage return
+        INLCR = libc::INLCR;
+        /// Ignore carriage return
+        IGNCR = libc::IGNCR;
+        /// Translate carriage return to newline
+        ICRNL = libc::ICRNL;
+        /// Output flow control (ctrl-s and ctrl-q)
+        IXON = libc::IXON;
+        /// Input flow control
+        IXOFF = libc::IXOFF;
+        /// Any character restarts stopped output
+        IXANY = libc::IXANY;
+        /// Ring the bell when the input queue is full
+        IMAXBEL = libc::IMAXBEL;
+        /// Input is UTF-8, so that erase works on characters
+        IUTF8 = libc::IUTF8;
+    }
+);
+flag_type!(
+    /// Output flags (`c_oflag`).
+    OutputFlags, FlagGroup::Output, {
+        /// Enable output processing
+        OPOST = libc::OPOST;
+        /// Translate newline to carriage return + newline
+        ONLCR = libc::ONLCR;
+        /// Translate carriage return to newline
+        OCRNL = libc::OCRNL;
+        /// Don't output carriage return at column 0
+        ONOCR = libc::ONOCR;
+        /// Newline performs a carriage return
+        ONLRET = libc::ONLRET;
+    }
+);
+flag_type!(
+    /// Control flags (`c_cflag`). The character size and baud rate also
+    /// live in this field; set them with [`TermiosBuilder::char_size()`]
+    /// and [`TermiosBuilder::baud()`].
+    ControlFlags, FlagGroup::Control, {
+        /// Enable parity generation and checking
+        PARENB = libc::PARENB;
+        /// Odd parity, even if unset
+        PARODD = libc::PARODD;
+        /// Mark or space parity
+        CMSPAR = libc::CMSPAR;
+        /// Two stop bits, one if unset
+        CSTOPB = libc::CSTOPB;
+        /// Enable the receiver
+        CREAD = libc::CREAD;
+        /// Ignore modem control lines
+        CLOCAL = libc::CLOCAL;
+        /// Hang up on last close
+        HUPCL = libc::HUPCL;
+        /// Hardware (RTS/CTS) flow control
+        CRTSCTS = libc::CRTSCTS;
+    }
+);
+flag_type!(
+    /// Local flags (`c_lflag`).
+    LocalFlags, FlagGroup::Local, {
+        /// Generate signals for the INTR, QUIT and SUSP characters
+        ISIG = libc::ISIG;
+        /// Canonical (line by line) input
+        ICANON = libc::ICANON;
+        /// Extended input processing, like ctrl-v
+        IEXTEN = libc::IEXTEN;
+        /// Echo input characters
+        ECHO = libc::ECHO;
+        /// ERASE erases the previous character
+        ECHOE = libc::ECHOE;
+        /// KILL erases the current line
+        ECHOK = libc::ECHOK;
+        /// Echo newline even if ECHO is unset
+        ECHONL = libc::ECHONL;
+        /// Echo control characters as ^X
+        ECHOCTL = libc::ECHOCTL;
+        /// KILL erases each character on the line
+        ECHOKE = libc::ECHOKE;
+        /// Don't flush the queues on INTR, QUIT and SUSP
+        NOFLSH = libc::NOFLSH;
+        /// Send SIGTTOU to background jobs that write to the terminal
+        TOSTOP = libc::TOSTOP;
+    }
+);
+
+/// Special characters recognized by the terminal driver. The timing
+/// values `VMIN` and `VTIME` are set with [`TermiosBuilder::read_min()`]
+/// and [`TermiosBuilder::read_timeout()`] instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ControlChar {
+    /// `VINTR`, sends SIGINT (default ctrl-c)
+    Intr,
+    /// `VQUIT`, sends SIGQUIT (default ctrl-\)
+    Quit,
+    /// `VERASE`, erases a character (default DEL)
+    Erase,
+    /// `VKILL`, erases the line (default ctrl-u)
+    Kill,
+    /// `VEOF`, end of file (default ctrl-d)
+    Eof,
+    /// `VEOL`, additional end of line character
+    Eol,
+    /// `VEOL2`, another end of line character
+    Eol2,
+    /// `VSTART`, restarts output (default ctrl-q)
+    Start,
+    /// `VSTOP`, stops output (default ctrl-s)
+    Stop,
+    /// `VSUSP`, sends SIGTSTP (default ctrl-z)
+    Susp,
+    /// `VREPRINT`, reprints the line (default ctrl-r)
+    Reprint,
+    /// `VWERASE`, erases a word (default ctrl-w)
+    WordErase,
+    /// `VLNEXT`, quotes the next character (default ctrl-v)
+    LiteralNext,
+    /// `VDISCARD`, toggles discarding output (default ctrl-o)
+    Discard,
+}
+impl ControlChar {
+    /// The index into `c_cc`.
+    pub fn index(&self) -> usize {
+        match self {
+            Self::Intr => libc::VINTR,
+            Self::Quit => libc::VQUIT,
+            Self::Erase => libc::VERASE,
+            Self::Kill => libc::VKILL,
+            Self::Eof => libc::VEOF,
+            Self::Eol => libc::VEOL,
+            Self::Eol2 => libc::VEOL2,
+            Self::Start => libc::VSTART,
+            Self::Stop => libc::VSTOP,
+            Self::Susp => libc::VSUSP,
+            Self::Reprint => libc::VREPRINT,
+            Self::WordErase => libc::VWERASE,
+            Self::LiteralNext => libc::VLNEXT,
+            Self::Discard => libc::VDISCARD,
+        }
+    }
+}
+
+/// Collects typed changes to terminal settings and validates them as a
+/// whole. Used through [`crate::Term::with_flags()`].
+///
+/// Enabling and disabling the same flag, assigning the same character to
+/// two signal characters, non-ASCII control characters, unsupported baud
+/// rates, different input and output baud rates and character sizes
+/// other than 5 to 8 are rejected.
+#[derive(Debug, Clone, Default)]
+pub struct TermiosBuilder {
+    set: [tcflag_t; 4],
+    clear: [tcflag_t; 4],
+    chars: Vec<(ControlChar, Option<u8>)>,
+    vmin: Option<u8>,
+    vtime: Option<Duration>,
+    char_size: Option<u8>,
+    ispeed: Option<u32>,
+    ospeed: Option<u32>,
+}
+impl TermiosBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn slot(group: FlagGroup) -> usize {
+        match group {
+            FlagGroup::Input => 0,
+            FlagGroup::Output => 1,
+            FlagGroup::Control => 2,
+            FlagGroup::Local => 3,
+        }
+    }
+    /// Sets the given flags.
+    pub fn enable<F: Flags>(&mut self, flags: F) -> &mut Self {
+        self.set[Self::slot(F::GROUP)] |= flags.bits();
+        self
+    }
+    /// Unsets the given flags.
+    pub fn disable<F: Flags>(&mut self, flags: F) -> &mut Self {
+        self.clear[Self::slot(F::GROUP)] |= flags.bits();
+        self
+    }
+    /// Assigns a control character. `None` disables it.
+    pub fn control_char(&mut self, which: ControlChar, value: Option<u8>) -> &mut Self {
+        self.chars.push((which, value));
+        self
+    }
+    /// Minimum number of bytes for a read to return in non-canonical mode
+    /// (`VMIN`).
+    pub fn read_min(&mut self, bytes: u8) -> &mut Self {
+        self.vmin = Some(bytes);
+        self
+    }
+    /// Read timeout in non-canonical mode (`VTIME`). Granularity is tenths
+    /// of a second, with a maximum of 25.5s; zero disables the timeout.
+    pub fn read_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.vtime = Some(timeout);
+        self
+    }
+    /// Number of data bits per character, 5 to 8.
+    pub fn char_size(&mut self, bits: u8) -> &mut Self {
+        self.char_size = Some(bits);
+        self
+    }
+    /// Sets both the input and output baud rates.
+    pub fn baud(&mut self, baud: u32) -> &mut Self {
+        self.ispeed = Some(baud);
+        self.ospeed = Some(baud);
+        self
+    }
+    /// Sets the input baud rate. Linux keeps a single rate for both
+    /// directions, so it has to be the same as any output baud rate.
+    pub fn input_baud(&mut self, baud: u32) -> &mut Self {
+        self.ispeed = Some(baud);
+        self
+    }
+    /// Sets the output baud rate. Linux keeps a single rate for both
+    /// directions, so it has to be the same as any input baud rate.
+    pub fn output_baud(&mut self, baud: u32) -> &mut Self {
+        self.ospeed = Some(baud);
+        self
+    }
+    /// Validates the changes and applies them to a copy of `base`.
+    pub fn build(&self, base: Termios) -> io::Result<Termios> {
+        let mut t: termios = base.into();
+        for group in FlagGroup::ALL {
+            let i = Self::slot(group);
+            let both = self.set[i] & self.clear[i];
+            if both != 0 {
+                let names: Vec<&str> = group
+                    .flags()
+                    .iter()
+                    .filter(|(_, f)| both & f != 0)
+                    .map(|(n, _)| *n)
+                    .collect();
+                return Err(invalid(format!(
+                    "flags both enabled and disabled: {}",
+                    names.join(", ")
+                )));
+            }
+        }
+        t.c_iflag = (t.c_iflag | self.set[0]) & !self.clear[0];
+        t.c_oflag = (t.c_oflag | self.set[1]) & !self.clear[1];
+        t.c_cflag = (t.c_cflag | self.set[2]) & !self.clear[2];
+        t.c_lflag = (t.c_lflag | self.set[3]) & !self.clear[3];
+        for (which, value) in self.chars.iter() {
+            if let Some(c) = value {
+                if !c.is_ascii() {
+                    return Err(invalid(format!("{which:?} must be an ASCII character")));
+                }
+            }
+            t.c_cc[which.index()] = value.unwrap_or(libc::_POSIX_VDISABLE);
+        }
+        let signal_chars = [ControlChar::Intr, ControlChar::Quit, ControlChar::Susp];
+        for (n, a) in signal_chars.iter().enumerate() {
+            for b in signal_chars[n + 1..].iter() {
+                let c = t.c_cc[a.index()];
+                if c != libc::_POSIX_VDISABLE && c == t.c_cc[b.index()] {
+                    return Err(invalid(format!("{a:?} and {b:?} are the same character")));
+                }
+            }
+        }
+        if let Some(vmin) = self.vmin {
+            t.c_cc[libc::VMIN] = vmin;
+        }
+        if let Some(vtime) = self.vtime {
+            t.c_cc[libc::VTIME] = (vtime.as_millis() / 100).min(255) as u8;
+        }
+        if let Some(bits) = self.char_size {
+            let size = match bits {
+                5 => libc::CS5,
+                6 => libc::CS6,
+                7 => libc::CS7,
+                8 => libc::CS8,
+                _ => return Err(invalid(format!("invalid character size {bits}"))),
+            };
+            t.c_cflag = (t.c_cflag & !libc::CSIZE) | size;
+        }
+        if let (Some(i), Some(o)) = (self.ispeed, self.ospeed) {
+            if i != o {
+                return Err(invalid(format!(
+                    "input baud rate {i} differs from output baud rate {o}"
+                )));
+            }
+        }
+        let speed = |baud| {
+            baud_to_speed(baud).ok_or_else(|| invalid(format!("unsupported baud rate {baud}")))
+        };
+        if let Some(baud) = self.ispeed {
+            let s = speed(baud)?;
+            unsafe { libc::cfsetispeed(&mut t, s) };
+        }
+        if let Some(baud) = self.ospeed {
+            let s = speed(baud)?;
+            unsafe { libc::cfsetospeed(&mut t, s) };
+        }
+        Ok(t.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `stty -g` of a typical Linux terminal at 38400 baud
+    const SANE: &str =
+        "500:5:bf:8a3b:3:1c:7f:15:4:0:1:0:11:13:1a:0:12:f:17:16:0:0:0:0:0:0:0:0:0:0:0:0:0:0:0:0";
+
+    fn build(b: &TermiosBuilder) -> io::Result<Termios> {
+        b.build(Termios::from_stty_g(SANE).unwrap())
+    }
+
+    #[test]
+    fn flags() {
+        let t = build(
+            TermiosBuilder::new()
+                .enable(InputFlags::IUTF8 | InputFlags::IXOFF)
+                .disable(LocalFlags::ECHO | LocalFlags::ICANON)
+                .enable(ControlFlags::PARENB)
+                .disable(OutputFlags::OPOST),
+        )
+        .unwrap();
+        assert!(t.is_set(FlagGroup::Input, libc::IUTF8 | libc::IXOFF));
+        assert!(t.is_set(FlagGroup::Input, libc::ICRNL));
+        assert!(!t.is_set(FlagGroup::Local, libc::ECHO));
+        assert!(!t.is_set(FlagGroup::Local, libc::ICANON));
+        assert!(t.is_set(FlagGroup::Local, libc::ISIG));
+        assert!(t.is_set(FlagGroup::Control, libc::PARENB));
+        assert!(!t.is_set(FlagGroup::Output, libc::OPOST));
+        assert!(t.is_set(FlagGroup::Output, libc::ONLCR));
+        assert_eq!(
+            format!("{:?}", LocalFlags::ECHO | LocalFlags::ISIG),
+            r#"LocalFlags(["isig", "echo"])"#
+        );
+    }
+
+    #[test]
+    fn control_chars() {
+        let t = build(
+            TermiosBuilder::new()
+                .control_char(ControlChar::Intr, Some(0x18))
+                .control_char(ControlChar::Discard, None)
+                .read_min(0)
+                .read_timeout(Duration::from_millis(1500))
+                .char_size(7)
+                .baud(9600),
+        )
+        .unwrap();
+        assert_eq!(t.control_char(libc::VINTR), 0x18);
+        assert_eq!(t.control_char(libc::VDISCARD), libc::_POSIX_VDISABLE);
+        assert_eq!(t.control_char(libc::VMIN), 0);
+        assert_eq!(t.control_char(libc::VTIME), 15);
+        assert_eq!(t.char_size(), 7);
+        assert_eq!(t.input_baud(), Some(9600));
+        assert_eq!(t.output_baud(), Some(9600));
+        let t = build(TermiosBuilder::new().read_timeout(Duration::from_secs(60))).unwrap();
+        assert_eq!(t.control_char(libc::VTIME), 255);
+        // disabled signal characters do not clash
+        build(
+            TermiosBuilder::new()
+                .control_char(ControlChar::Intr, None)
+                .control_char(ControlChar::Quit, None),
+        )
+        .unwrap();
+        let t = build(TermiosBuilder::new().input_baud(1200).output_baud(1200)).unwrap();
+        assert_eq!(t.output_baud(), Some(1200));
+    }
+
+    #[test]
+    fn invalid_changes() {
+        let errors = [
+            TermiosBuilder::new()
+                .enable(LocalFlags::ECHO | LocalFlags::ISIG)
+                .disable(LocalFlags::ECHO)
+                .clone(),
+            TermiosBuilder::new()
+                .control_char(ControlChar::Erase, Some(0xe9))
+                .clone(),
+            TermiosBuilder::new()
+                .control_char(ControlChar::Quit, Some(3))
+                .clone(),
+            TermiosBuilder::new()
+                .control_char(ControlChar::Intr, Some(0x1a))
+                .control_char(ControlChar::Susp, Some(0x1a))
+                .clone(),
+            TermiosBuilder::new().char_size(9).clone(),
+            TermiosBuilder::new().baud(12345).clone(),
+            TermiosBuilder::new().output_baud(12345).clone(),
+            TermiosBuilder::new()
+                .input_baud(9600)
+                .output_baud(19200)
+                .clone(),
+        ];
+        let messages: Vec<String> = errors
+            .iter()
+            .map(|b| {
+                let e = build(b).unwrap_err();
+                assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+                e.to_string()
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "flags both enabled and disabled: echo",
+                "Erase must be an ASCII character",
+                "Intr and Quit are the same character",
+                "Intr and Susp are the same character",
+                "invalid character size 9",
+                "unsupported baud rate 12345",
+                "unsupported baud rate 12345",
+                "input baud rate 9600 differs from output baud rate 19200",
+            ]
+        );
+    }
+}
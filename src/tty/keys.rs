@@ -0,0 +1,342 @@
+//! Decoding of raw terminal input into keys, paste and resize events.
+use std::collections::VecDeque;
+
+/// Sent by the terminal before pasted text when bracketed paste is enabled.
+const PASTE_START: &[u8] = b"\x1b[200~";
+/// Sent by the terminal after pasted text when bracketed paste is enabled.
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// A decoded keystroke.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Key {
+    /// A printable character, including non-ASCII UTF-8 input
+    Char(char),
+    /// A control character, reported as its letter, ie. ctrl-c is `Ctrl('c')`
+    Ctrl(char),
+    /// A character preceded by ESC, which is how most terminals report
+    /// the alt (meta) modifier
+    Alt(char),
+    Enter,
+    Tab,
+    BackTab,
+    Backspace,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    /// Function key, numbered from 1
+    F(u8),
+    /// A sequence that could not be decoded
+    Unknown(Vec<u8>),
+}
+
+/// An event produced from terminal input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TermEvent {
+    Key(Key),
+    /// The terminal window changed size
+    Resize {
+        cols: u16,
+        rows: u16,
+    },
+    /// Text received between bracketed paste markers. See
+    /// [`crate::Term::enable_bracketed_paste()`].
+    Paste(Vec<u8>),
+}
+
+/// Turns bytes read from a terminal in raw mode into [`TermEvent`]s.
+///
+/// Bytes are fed in as they are read, and complete events are taken out
+/// with [`Self::next_event()`]. Incomplete escape sequences and UTF-8
+/// characters are kept until the rest of the bytes arrive. A lone ESC at
+/// the end of the input is reported as [`Key::Esc`], since terminals send
+/// escape sequences in a single write.
+///
+/// Example:
+///
+/// ```
+/// let mut d = KeyDecoder::new();
+/// d.feed(b"a\x1b[A");
+/// assert_eq!(d.next_event(), Some(TermEvent::Key(Key::Char('a'))));
+/// assert_eq!(d.next_event(), Some(TermEvent::Key(Key::Up)));
+/// assert_eq!(d.next_event(), None);
+/// ```
+#[derive(Debug, Default)]
+pub struct KeyDecoder {
+    buf: VecDeque<u8>,
+    paste: Option<Vec<u8>>,
+}
+impl KeyDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Appends input bytes to the decoder.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend(bytes);
+    }
+    /// Returns true if there are bytes that have not been decoded yet.
+    pub fn has_pending(&self) -> bool {
+        !self.buf.is_empty()
+    }
+    /// Decodes the next event. Returns `None` if more input is needed.
+    pub fn next_event(&mut self) -> Option<TermEvent> {
+        let bytes = self.buf.make_contiguous();
+        if let Some(paste) = self.paste.as_mut() {
+            // collect everything up to the end marker
+            match find(bytes, PASTE_END) {
+                Some(end) => {
+                    paste.extend_from_slice(&bytes[..end]);
+                    self.buf.drain(..end + PASTE_END.len());
+                    return self.paste.take().map(TermEvent::Paste);
+                }
+                None => {
+                    // keep a possible partial end marker in the buffer
+                    let keep = partial_suffix(bytes, PASTE_END);
+                    let take = bytes.len() - keep;
+                    paste.extend_from_slice(&bytes[..take]);
+                    self.buf.drain(..take);
+                    return None;
+                }
+            }
+        }
+        if bytes.starts_with(PASTE_START) {
+            self.buf.drain(..PASTE_START.len());
+            self.paste = Some(Vec::new());
+            return self.next_event();
+        }
+        let (key, len) = decode(bytes)?;
+        self.buf.drain(..len);
+        Some(TermEvent::Key(key))
+    }
+}
+
+/// Like [`KeyDecoder::next_event()`], but decodes from `bytes` where they
+/// are instead of copying them into a buffer, for input that should not
+/// linger in memory, such as a password. A bracketed paste marker gives
+/// `None` and toggles `in_paste`, and the pasted text comes out as keys.
+/// Returns `None` as the whole result if more input is needed.
+pub(crate) fn decode_in_place(bytes: &[u8], in_paste: &mut bool) -> Option<(Option<Key>, usize)> {
+    let marker = match in_paste {
+        true => PASTE_END,
+        false => PASTE_START,
+    };
+    if bytes.starts_with(marker) {
+        *in_paste = !*in_paste;
+        return Some((None, marker.len()));
+    }
+    decode(bytes).map(|(key, len)| (Some(key), len))
+}
+
+/// Decodes one key from the front of `bytes`, returning it along with the
+/// number of bytes consumed.
+fn decode(bytes: &[u8]) -> Option<(Key, usize)> {
+    let first = *bytes.first()?;
+    let key = match first {
+        27 => return decode_escape(bytes),
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        8 | 127 => Key::Backspace,
+        1..=26 => Key::Ctrl((first - 1 + b'a') as char),
+        0 => Key::Ctrl('@'),
+        28..=31 => Key::Ctrl((first - 28 + b'\\') as char),
+        0x20..=0x7e => Key::Char(first as char),
+        _ => return decode_utf8(bytes),
+    };
+    Some((key, 1))
+}
+
+fn decode_utf8(bytes: &[u8]) -> Option<(Key, usize)> {
+    let len = match bytes[0] {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Some((Key::Unknown(bytes[..1].to_vec()), 1)),
+    };
+    if bytes.len() < len {
+        return None;
+    }
+    match std::str::from_utf8(&bytes[..len]) {
+        Ok(s) => s.chars().next().map(|c| (Key::Char(c), len)),
+        Err(_) => Some((Key::Unknown(bytes[..1].to_vec()), 1)),
+    }
+}
+
+fn decode_escape(bytes: &[u8]) -> Option<(Key, usize)> {
+    match bytes.get(1) {
+        None => Some((Key::Esc, 1)),
+        Some(b'[') => decode_csi(bytes),
+        Some(b'O') => {
+            let key = match bytes.get(2)? {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Right,
+                b'D' => Key::Left,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                b'P' => Key::F(1),
+                b'Q' => Key::F(2),
+                b'R' => Key::F(3),
+                b'S' => Key::F(4),
+                _ => Key::Unknown(bytes[..3].to_vec()),
+            };
+            Some((key, 3))
+        }
+        Some(27) => Some((Key::Esc, 1)),
+        Some(_) => match decode(&bytes[1..])? {
+            (Key::Char(c), len) => Some((Key::Alt(c), len + 1)),
+            _ => Some((Key::Esc, 1)),
+        },
+    }
+}
+
+fn decode_csi(bytes: &[u8]) -> Option<(Key, usize)> {
+    // parameters and intermediates are 0x20..=0x3f, the final byte is 0x40..=0x7e
+    let end = bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b))? + 2;
+    let params = &bytes[2..end];
+    let len = end + 1;
+    let key = match (bytes[end], params) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) => Key::Home,
+        (b'F', _) => Key::End,
+        (b'Z', _) => Key::BackTab,
+        (b'~', p) => {
+            let n = p
+                .split(|b| *b == b';')
+                .next()
+                .and_then(|n| std::str::from_utf8(n).ok())
+                .and_then(|n| n.parse::<u8>().ok());
+            match n {
+                Some(1 | 7) => Key::Home,
+                Some(2) => Key::Insert,
+                Some(3) => Key::Delete,
+                Some(4 | 8) => Key::End,
+                Some(5) => Key::PageUp,
+                Some(6) => Key::PageDown,
+                Some(n @ 11..=15) => Key::F(n - 10),
+                Some(n @ 17..=21) => Key::F(n - 11),
+                Some(n @ 23..=24) => Key::F(n - 12),
+                _ => Key::Unknown(bytes[..len].to_vec()),
+            }
+        }
+        _ => Key::Unknown(bytes[..len].to_vec()),
+    };
+    Some((key, len))
+}
+
+/// Position of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Length of the longest suffix of `bytes` that is a prefix of `marker`
+fn partial_suffix(bytes: &[u8], marker: &[u8]) -> usize {
+    (1..marker.len())
+        .rev()
+        .find(|&n| n <= bytes.len() && bytes.ends_with(&marker[..n]))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(input: &[u8]) -> Vec<TermEvent> {
+        let mut d = KeyDecoder::new();
+        d.feed(input);
+        std::iter::from_fn(|| d.next_event()).collect()
+    }
+    fn key(k: Key) -> TermEvent {
+        TermEvent::Key(k)
+    }
+
+    #[test]
+    fn control_keys() {
+        assert_eq!(
+            keys(b"\r\n\t\x7f\x08\x03\x00\x1c"),
+            [
+                key(Key::Enter),
+                key(Key::Enter),
+                key(Key::Tab),
+                key(Key::Backspace),
+                key(Key::Backspace),
+                key(Key::Ctrl('c')),
+                key(Key::Ctrl('@')),
+                key(Key::Ctrl('\\')),
+            ]
+        );
+    }
+
+    #[test]
+    fn escape_sequences() {
+        assert_eq!(
+            keys(b"\x1b[A\x1bOB\x1b[3~\x1b[15~\x1b[1;5C\x1bOP\x1b[Z\x1bx"),
+            [
+                key(Key::Up),
+                key(Key::Down),
+                key(Key::Delete),
+                key(Key::F(5)),
+                key(Key::Right),
+                key(Key::F(1)),
+                key(Key::BackTab),
+                key(Key::Alt('x')),
+            ]
+        );
+        assert_eq!(keys(b"\x1b[99~"), [key(Key::Unknown(b"\x1b[99~".to_vec()))]);
+    }
+
+    #[test]
+    fn lone_and_double_esc() {
+        assert_eq!(keys(b"\x1b"), [key(Key::Esc)]);
+        assert_eq!(keys(b"\x1b\x1b[D"), [key(Key::Esc), key(Key::Left)]);
+    }
+
+    #[test]
+    fn incomplete_input_waits() {
+        let mut d = KeyDecoder::new();
+        d.feed(b"\x1b[1");
+        assert_eq!(d.next_event(), None);
+        assert!(d.has_pending());
+        d.feed(b"5~");
+        assert_eq!(d.next_event(), Some(key(Key::F(5))));
+
+        // "é" split across reads
+        d.feed(&[0xc3]);
+        assert_eq!(d.next_event(), None);
+        d.feed(&[0xa9]);
+        assert_eq!(d.next_event(), Some(key(Key::Char('é'))));
+        assert!(!d.has_pending());
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(
+            keys(&[0xff, 0xc3, b'a']),
+            [
+                key(Key::Unknown(vec![0xff])),
+                key(Key::Unknown(vec![0xc3])),
+                key(Key::Char('a')),
+            ]
+        );
+    }
+
+    #[test]
+    fn bracketed_paste() {
+        let mut d = KeyDecoder::new();
+        d.feed(b"x\x1b[200~a\x1b[Ab\x1b[20");
+        assert_eq!(d.next_event(), Some(key(Key::Char('x'))));
+        assert_eq!(d.next_event(), None);
+        d.feed(b"1~y");
+        assert_eq!(d.next_event(), Some(TermEvent::Paste(b"a\x1b[Ab".to_vec())));
+        assert_eq!(d.next_event(), Some(key(Key::Char('y'))));
+    }
+}
@@ -1,43 +1,104 @@
 use std::{
     ffi::CStr,
     io::{self, Read},
+    ops::{Deref, DerefMut},
 };
+mod askpass;
+mod generate;
+#[cfg(feature = "crypt")]
+mod hash;
+mod pinentry;
+mod policy;
+mod secure;
+mod source;
+pub use askpass::{serve_askpass, Askpass};
+pub use generate::{PassphraseGenerator, PasswordGenerator};
+#[cfg(feature = "crypt")]
+pub use hash::HashMethod;
+pub use pinentry::{Pinentry, PinentryError};
+pub use policy::{CharClass, PasswordPolicy, PolicyViolation};
+pub use source::{read_password, PasswordSource};
+use secure::SecureBuf;
 
-/// Buffer size was selected to hold at least 127 UTF-8 characters.
+/// Default buffer size of a [`Password`]. It was selected to hold at least
+/// 127 UTF-8 characters.
 pub const PASSWORD_BUFFER_LEN: usize = 512;
 /// Type that owns a buffer on the heap that will not reallocate. It is
 /// intended to hold user entered password data. When dropped, it
 /// overwrites the buffer contents with nul bytes.
 ///
 /// The fixed buffer size, defined by [`PASSWORD_BUFFER_LEN`], should
-/// allow it to hold very long UTF8 password data.
+/// allow it to hold very long UTF8 password data. Tools that handle long
+/// passphrases or API tokens can pick another size with the `N` parameter,
+/// which includes the terminating nul byte. Input that does not fit is
+/// dropped, and [`Self::is_truncated()`] reports it.
+///
+/// The buffer lives in its own memory mapping, which is locked into RAM
+/// with `mlock` so that it is never written to swap, and excluded from
+/// core dumps. Locking fails when `RLIMIT_MEMLOCK` is too low; check
+/// [`Self::is_locked()`] if that matters. For extra protection, the
+/// buffer can be made inaccessible between uses with [`Self::protect()`].
 ///
 /// Example:
 /// ```
 /// let mut pw = Password::new();
 /// pw.read_line(std::io::stdin())?;
-/// if pw.as_str() == "SECRET" {
+/// // compared in constant time
+/// if pw == "SECRET" {
 ///     println!("password is correct");
 /// } else {
 ///     println!("wrong password");
 /// }
 /// ```
-pub struct Password {
-    buf: Box<[u8; PASSWORD_BUFFER_LEN]>,
+///
+/// A larger buffer:
+/// ```
+/// let mut token = Password::<4096>::sized();
+/// t.prompt_for_password_into("API token", &mut token)?;
+/// if token.is_truncated() {
+///     eprintln!("token too long");
+/// }
+/// ```
+pub struct Password<const N: usize = PASSWORD_BUFFER_LEN> {
+    buf: SecureBuf,
+    truncated: bool,
 }
 impl Password {
+    /// Creates a password with the default buffer size,
+    /// [`PASSWORD_BUFFER_LEN`]. Panics if the buffer cannot be mapped; see
+    /// [`Self::try_new()`].
     pub fn new() -> Self {
-        Self {
-            buf: Box::new([0; PASSWORD_BUFFER_LEN]),
-        }
+        Self::sized()
+    }
+    /// Like [`Self::new()`], but fails instead of panicking if the buffer
+    /// cannot be mapped, as under a low `RLIMIT_AS`.
+    pub fn try_new() -> io::Result<Self> {
+        Self::try_sized()
+    }
+}
+impl<const N: usize> Password<N> {
+    /// Creates a password with a buffer of `N` bytes, including the
+    /// terminating nul byte. Panics if the buffer cannot be mapped; see
+    /// [`Self::try_sized()`].
+    pub fn sized() -> Self {
+        Self::try_sized().expect("Should have mapped memory for a password buffer")
+    }
+    /// Like [`Self::sized()`], but fails instead of panicking if the
+    /// buffer cannot be mapped.
+    pub fn try_sized() -> io::Result<Self> {
+        assert!(N > 0, "Password buffer requires room for the nul byte");
+        Ok(Self {
+            buf: SecureBuf::new(N)?,
+            truncated: false,
+        })
     }
     // /// Returns true if the buffer's last byte is a nul
     // pub fn is_nul_terminated(&self) -> bool {
-    //     self.buf[PASSWORD_BUFFER_LEN - 1] == 0
+    //     self.buf[N - 1] == 0
     // }
     /// Returns a `&CStr` to the buffer data.
     pub fn as_cstr(&self) -> &CStr {
-        CStr::from_bytes_until_nul(self.buf.as_slice())
+        CStr::from_bytes_until_nul(&self.buf)
             .expect("Password buffer requires terminating nul byte")
     }
     /// Returns a `&str` if the buffer contains UTF-8 data.
@@ -45,36 +106,122 @@ impl Password {
         self.as_cstr().to_str()
     }
     /// Convenience method for reading a newline terminated input from the
-    /// given Reader. Removes the trailing newline from the input. Inputs
-    /// larger than [`PASSWORD_BUFFER_LEN`] - 1 are truncated.
+    /// given Reader. Removes the trailing newline from the input, and
+    /// discards anything after it that was read along with it. Previous
+    /// contents are cleared first. Inputs larger than `N - 1` bytes are
+    /// truncated: the rest of the line is read and discarded, so that it
+    /// is not left for the next reader, and [`Self::is_truncated()`]
+    /// returns true.
     pub fn read_line(&mut self, mut fd: &mut impl Read) -> io::Result<()> {
+        // a shorter line must not leave the end of a previous one behind
+        self.clear();
         let mut index = 0;
         loop {
-            let buf = &mut self.buf[index..PASSWORD_BUFFER_LEN - 1];
-            let n = fd.read(buf)?;
-            if n == 0 {
+            let buf = &mut self.buf[index..N - 1];
+            if buf.is_empty() {
+                self.truncated = discard_line(fd)?;
                 break;
             }
-            index += n;
-            if self.buf[index - 1] == b'\n' {
-                // replace the trailing newline with a nul byte
-                self.buf[index - 1] = 0;
+            let n = fd.read(buf)?;
+            if n == 0 {
                 break;
             }
-            // truncate large inputs
-            if index >= PASSWORD_BUFFER_LEN - 1 {
+            // files and pipes may return more than one line per read
+            if let Some(pos) = buf[..n].iter().position(|b| *b == b'\n') {
+                // replace the newline and anything after it with nul bytes
+                buf[pos..n].fill(0);
                 break;
             }
+            index += n;
         }
         Ok(())
     }
+    /// Returns true if input was dropped because it did not fit in the
+    /// buffer. The flag is reset by [`Self::read_line()`] and
+    /// [`Self::clear()`].
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+    pub(crate) fn set_truncated(&mut self) {
+        self.truncated = true;
+    }
     /// Returns a slice of bytes containing the password data without a
     /// trailing nul byte. Equivalent to `Self::as_cstr().to_bytes()`.
     pub fn to_bytes(&self) -> &[u8] {
         self.as_cstr().to_bytes()
     }
+    /// Returns the length of the password data in bytes.
+    pub fn len(&self) -> usize {
+        self.to_bytes().len()
+    }
+    /// Returns true if no password data has been entered.
+    pub fn is_empty(&self) -> bool {
+        self.buf[0] == 0
+    }
+    /// Overwrites the whole buffer with nul bytes.
+    pub fn clear(&mut self) {
+        self.buf.fill(0);
+        self.truncated = false;
+    }
+    /// Removes the last UTF-8 character, overwriting its bytes with nul
+    /// bytes. Returns false if the password was already empty.
+    pub fn pop_char(&mut self) -> bool {
+        let mut len = self.len();
+        if len == 0 {
+            return false;
+        }
+        // remove continuation bytes, then the leading byte
+        while len > 0 {
+            len -= 1;
+            let b = self.buf[len];
+            self.buf[len] = 0;
+            if b & 0xc0 != 0x80 {
+                break;
+            }
+        }
+        true
+    }
+    /// Returns the number of UTF-8 characters in the password data.
+    pub fn char_count(&self) -> usize {
+        self.to_bytes().iter().filter(|b| **b & 0xc0 != 0x80).count()
+    }
+    /// Returns true if the buffer is locked into memory, so that it cannot
+    /// be written to swap. Locking fails if the `RLIMIT_MEMLOCK` resource
+    /// limit is too low.
+    pub fn is_locked(&self) -> bool {
+        self.buf.is_locked()
+    }
+    /// Makes the buffer inaccessible (`PROT_NONE`) until
+    /// [`Self::unprotect()`] is called or the password is accessed through
+    /// [`Self::reveal()`]. While protected, any other method that reads or
+    /// writes the password data panics. Example:
+    ///
+    /// ```
+    /// let mut pw = Password::new();
+    /// pw.read_line(&mut std::io::stdin())?;
+    /// pw.protect()?;
+    /// // …later
+    /// let key = derive_key(pw.reveal()?.to_bytes());
+    /// ```
+    pub fn protect(&mut self) -> io::Result<()> {
+        self.buf.protect()
+    }
+    /// Makes a protected buffer accessible again.
+    pub fn unprotect(&mut self) -> io::Result<()> {
+        self.buf.unprotect()
+    }
+    /// Returns true if the buffer is currently protected.
+    pub fn is_protected(&self) -> bool {
+        self.buf.is_protected()
+    }
+    /// Temporarily makes a protected password accessible. It is protected
+    /// again when the returned guard is dropped.
+    pub fn reveal(&mut self) -> io::Result<Revealed<'_, N>> {
+        self.unprotect()?;
+        Ok(Revealed(self))
+    }
     /// Returns a mutable slice for the buffer. The slice size is one less
-    /// than [`PASSWORD_BUFFER_LEN`] so that the buffer will always have a
+    /// than the buffer size `N` so that the buffer will always have a
     /// terminal nul byte. Useful if you would rather roll your own input
     /// routine and just need a buffer for the secret. Example:
     /// ```
@@ -84,15 +231,176 @@ impl Password {
     /// let bytes_read = stdin.read(pw.as_mut_slice()).unwrap();
     /// ```
     pub fn as_mut_slice(&mut self) -> &mut [u8] {
-        &mut self.buf.as_mut_slice()[0..PASSWORD_BUFFER_LEN - 1]
+        &mut self.buf[0..N - 1]
+    }
+}
+
+/// Reads and discards bytes up to and including the next newline, one at a
+/// time so that nothing past the line is consumed. Returns true if
+/// anything other than the newline was discarded.
+fn discard_line(fd: &mut impl Read) -> io::Result<bool> {
+    let mut discarded = false;
+    let mut b = [0u8; 1];
+    while fd.read(&mut b)? == 1 {
+        if b[0] == b'\n' {
+            break;
+        }
+        discarded = true;
+    }
+    b[0] = 0;
+    Ok(discarded)
+}
+
+/// Passwords compare in time that depends only on their lengths, not on
+/// their contents, so that comparing a guess against a secret does not
+/// reveal how much of the guess was right.
+impl<const N: usize, const M: usize> PartialEq<Password<M>> for Password<N> {
+    fn eq(&self, other: &Password<M>) -> bool {
+        ct_eq(self.to_bytes(), other.to_bytes())
+    }
+}
+impl<const N: usize> Eq for Password<N> {}
+impl<const N: usize> PartialEq<[u8]> for Password<N> {
+    fn eq(&self, other: &[u8]) -> bool {
+        ct_eq(self.to_bytes(), other)
+    }
+}
+impl<const N: usize> PartialEq<&[u8]> for Password<N> {
+    fn eq(&self, other: &&[u8]) -> bool {
+        ct_eq(self.to_bytes(), other)
+    }
+}
+impl<const N: usize> PartialEq<str> for Password<N> {
+    fn eq(&self, other: &str) -> bool {
+        ct_eq(self.to_bytes(), other.as_bytes())
+    }
+}
+impl<const N: usize> PartialEq<&str> for Password<N> {
+    fn eq(&self, other: &&str) -> bool {
+        ct_eq(self.to_bytes(), other.as_bytes())
+    }
+}
+
+/// Borrows a protected [`Password`] while it is temporarily accessible.
+/// Returned by [`Password::reveal()`]. The password is protected again
+/// when this is dropped.
+pub struct Revealed<'a, const N: usize = PASSWORD_BUFFER_LEN>(&'a mut Password<N>);
+impl<const N: usize> Deref for Revealed<'_, N> {
+    type Target = Password<N>;
+
+    fn deref(&self) -> &Password<N> {
+        self.0
+    }
+}
+impl<const N: usize> DerefMut for Revealed<'_, N> {
+    fn deref_mut(&mut self) -> &mut Password<N> {
+        self.0
     }
 }
-impl Drop for Password {
+impl<const N: usize> Drop for Revealed<'_, N> {
     fn drop(&mut self) {
-        self.buf.fill(0);
-        // ensure that the compiler and the hardware cannot defer the fill
-        use std::sync::atomic::{self, Ordering};
-        atomic::compiler_fence(Ordering::SeqCst);
-        atomic::fence(Ordering::SeqCst);
+        _ = self.0.protect();
+    }
+}
+
+/// Compares two byte slices in time that depends only on their lengths,
+/// not on their contents.
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    let len = a.len().max(b.len());
+    let mut diff = a.len() ^ b.len();
+    for i in 0..len {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        diff |= (x ^ y) as usize;
+    }
+    // keep the compiler from short-circuiting the loop
+    std::hint::black_box(diff) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_line() {
+        let mut pw = Password::<8>::sized();
+        pw.read_line(&mut &b"secret\nnext"[..]).unwrap();
+        assert_eq!(pw.as_str(), Ok("secret"));
+        // no newline at the end, and shorter than the previous line
+        pw.read_line(&mut &b"abc"[..]).unwrap();
+        assert_eq!(pw.as_str(), Ok("abc"));
+        assert!(!pw.is_truncated());
+    }
+
+    #[test]
+    fn read_line_truncates() {
+        let mut pw = Password::<8>::sized();
+        let mut input = &b"longpassword\nrest\n"[..];
+        pw.read_line(&mut input).unwrap();
+        assert_eq!(pw.as_str(), Ok("longpas"));
+        assert!(pw.is_truncated());
+        assert_eq!(input, b"rest\n");
+        pw.read_line(&mut input).unwrap();
+        assert_eq!(pw.as_str(), Ok("rest"));
+        assert!(!pw.is_truncated());
+    }
+
+    #[test]
+    fn editing() {
+        let mut pw = Password::<16>::sized();
+        pw.read_line(&mut "a\u{e9}\n".as_bytes()).unwrap();
+        assert_eq!(pw.char_count(), 2);
+        assert!(pw.pop_char());
+        assert_eq!(pw.as_str(), Ok("a"));
+        assert!(pw.pop_char());
+        assert!(!pw.pop_char());
+        assert!(pw.is_empty());
+    }
+
+    #[test]
+    fn reveal() {
+        let mut pw = Password::<8>::try_sized().unwrap();
+        pw.read_line(&mut &b"secret\n"[..]).unwrap();
+        pw.protect().unwrap();
+        assert!(pw.is_protected());
+        {
+            let revealed = pw.reveal().unwrap();
+            assert!(!revealed.is_protected());
+            assert_eq!(revealed.as_str(), Ok("secret"));
+        }
+        // protected again when the guard is dropped
+        assert!(pw.is_protected());
+        pw.unprotect().unwrap();
+        assert_eq!(pw.as_str(), Ok("secret"));
+    }
+
+    #[test]
+    fn constant_time_eq() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(b"secret", b"secret"));
+        assert!(!ct_eq(b"secret", b"secreT"));
+        assert!(!ct_eq(b"secret", b"secret1"));
+        assert!(!ct_eq(b"secret", b""));
+        // a difference in length must not cancel one in the contents
+        assert!(!ct_eq(b"\x00", b""));
+    }
+
+    #[test]
+    fn compare() {
+        let mut pw = Password::<16>::sized();
+        pw.read_line(&mut &b"secret\n"[..]).unwrap();
+        assert!(pw == "secret");
+        assert!(pw == *"secret");
+        let bytes: &[u8] = b"secret";
+        assert!(pw == bytes);
+        assert!(pw == *bytes);
+        assert!(pw != "secreT");
+        assert!(pw != "secre");
+        assert!(pw != "secrets");
+        let mut other = Password::<8>::sized();
+        other.read_line(&mut &b"secret\n"[..]).unwrap();
+        assert!(pw == other);
+        other.read_line(&mut &b"secreT\n"[..]).unwrap();
+        assert!(pw != other);
     }
 }
@@ -0,0 +1,196 @@
+//! Compatibility with `SSH_ASKPASS`-style password helper programs, in
+//! both directions.
+use super::Password;
+use crate::tty::Term;
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::fd::{AsFd, AsRawFd},
+    process::{Command, Stdio},
+};
+
+/// An external askpass program, such as `ssh-askpass` or `ksshaskpass`.
+/// It is run with the prompt as its only argument, and prints the password
+/// followed by a newline on stdout. A non-zero exit status means the user
+/// cancelled. Example:
+///
+/// ```
+/// let askpass = Askpass::from_env().unwrap_or(Askpass::new("ssh-askpass"));
+/// let pw = askpass.ask("Password for backup server: ")?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Askpass {
+    program: OsString,
+}
+impl Askpass {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            program: program.as_ref().to_os_string(),
+        }
+    }
+    /// Uses `$SUDO_ASKPASS`, or `$SSH_ASKPASS` if that is not set. Returns
+    /// `None` if neither is set or they are empty.
+    pub fn from_env() -> Option<Self> {
+        ["SUDO_ASKPASS", "SSH_ASKPASS"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .find(|p| !p.is_empty())
+            .map(Self::new)
+    }
+    pub fn program(&self) -> &OsStr {
+        &self.program
+    }
+    /// Runs the program and reads its first line of output into a
+    /// [`Password`]. Fails with an error of kind `PermissionDenied` if the
+    /// program exits with a non-zero status.
+    pub fn ask(&self, prompt: impl AsRef<OsStr>) -> io::Result<Password> {
+        let mut child = Command::new(&self.program)
+            .arg(prompt)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut pw = Password::try_new()?;
+        let read = match child.stdout.take() {
+            Some(mut stdout) => pw.read_line(&mut stdout),
+            None => Ok(()),
+        };
+        let status = child.wait()?;
+        read?;
+        if !status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} exited with {status}", self.program.to_string_lossy()),
+            ));
+        }
+        Ok(pw)
+    }
+    /// Sets up `cmd` so that ssh, sudo (with `-A`) and git use this program
+    /// to ask for passwords, even when they have a terminal.
+    pub fn configure<'a>(&self, cmd: &'a mut Command) -> &'a mut Command {
+        cmd.env("SSH_ASKPASS", &self.program)
+            .env("SSH_ASKPASS_REQUIRE", "force")
+            .env("SUDO_ASKPASS", &self.program)
+            .env("GIT_ASKPASS", &self.program)
+    }
+}
+
+/// Acts as an askpass program: prompts for a password on `/dev/tty`, and
+/// writes it followed by a newline to stdout. Trailing colons and spaces
+/// in `prompt` are removed, since the prompt gets a `": "` appended.
+///
+/// A binary can point [`Askpass::configure()`] at itself, and use an
+/// environment variable to tell that it was started as the helper:
+///
+/// ```
+/// fn main() -> std::io::Result<()> {
+///     if std::env::var_os("MYTOOL_ASKPASS").is_some() {
+///         let prompt = std::env::args().nth(1).unwrap_or_default();
+///         return serve_askpass(&prompt);
+///     }
+///     let me = std::env::current_exe()?;
+///     let mut cmd = std::process::Command::new("ssh");
+///     Askpass::new(me).configure(&mut cmd).env("MYTOOL_ASKPASS", "1");
+///     cmd.arg("backup-server").status()?;
+///     Ok(())
+/// }
+/// ```
+pub fn serve_askpass(prompt: &str) -> io::Result<()> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let mut term = Term::new(tty.try_clone()?, tty)?;
+    // written straight to the descriptor, since the buffer of io::stdout()
+    // would keep a copy of the password that is never zeroed
+    io::stdout().flush()?;
+    let stdout = File::from(io::stdout().as_fd().try_clone_to_owned()?);
+    answer(&mut term, prompt, stdout)
+}
+
+fn answer<I: Read, O: AsRawFd + Write>(
+    term: &mut Term<I, O>,
+    prompt: &str,
+    mut out: impl Write,
+) -> io::Result<()> {
+    let prompt = prompt.trim_end().trim_end_matches(':');
+    let prompt = if prompt.is_empty() {
+        "Password"
+    } else {
+        prompt
+    };
+    let pw = term.prompt_for_password(prompt)?;
+    out.write_all(pw.to_bytes())?;
+    out.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tty::openpty;
+    use std::{
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+        thread,
+        time::Duration,
+    };
+
+    fn fake_askpass(name: &str, body: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+    fn ask(program: &Path, prompt: &str) -> io::Result<Password> {
+        // another test forking while the script was open for writing
+        // keeps it busy until that child execs
+        loop {
+            match Askpass::new(program).ask(prompt) {
+                Err(e) if e.raw_os_error() == Some(libc::ETXTBSY) => {
+                    thread::sleep(Duration::from_millis(10))
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[test]
+    fn ask_program() {
+        // the prompt is the only argument, and only the first line counts
+        let path = fake_askpass("fake-askpass-ok", r#"printf '%s\nnext\n' "$1$#""#);
+        let pw = ask(&path, "Password: ");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pw.unwrap().as_str(), Ok("Password: 1"));
+    }
+
+    #[test]
+    fn ask_cancelled() {
+        let path = fake_askpass("fake-askpass-cancel", "echo partial; exit 1");
+        let e = ask(&path, "Password").err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn serve() {
+        for (prompt, shown) in [
+            ("Passphrase for key: ", "Passphrase for key: "),
+            (": ", "Password: "),
+        ] {
+            let (mut master, slave) = openpty().unwrap();
+            let mut term = Term::new(slave.try_clone().unwrap(), slave).unwrap();
+            let typist = thread::spawn(move || {
+                let mut out = Vec::new();
+                let mut buf = [0u8; 64];
+                while !out.ends_with(b": ") {
+                    let n = master.read(&mut buf).unwrap();
+                    out.extend_from_slice(&buf[..n]);
+                }
+                master.write_all(b"secret\n").unwrap();
+                // closing the master would hang up the terminal
+                (out, master)
+            });
+            let mut out = Vec::new();
+            answer(&mut term, prompt, &mut out).unwrap();
+            assert_eq!(out, b"secret\n");
+            assert_eq!(typist.join().unwrap().0, shown.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! Random passwords and passphrases, generated straight into a
+//! [`Password`] buffer.
+use super::{CharClass, Password};
+use std::{
+    ffi::c_void,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+const SYMBOLS: &[u8] = b"!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+/// Characters that are easily confused with each other in some fonts
+const SIMILAR: &[u8] = b"0O1lI|`'\"";
+
+fn alphabet(class: CharClass) -> &'static [u8] {
+    match class {
+        CharClass::Lowercase => b"abcdefghijklmnopqrstuvwxyz",
+        CharClass::Uppercase => b"ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+        CharClass::Digit => b"0123456789",
+        CharClass::Symbol => SYMBOLS,
+    }
+}
+
+/// Generates random passwords from ASCII character classes. Every
+/// password contains at least one character from each selected class, so
+/// that it passes a [`super::PasswordPolicy`] requiring them. Example:
+///
+/// ```
+/// let pw = PasswordGenerator::new(20)
+///     .include(CharClass::Lowercase)
+///     .include(CharClass::Digit)
+///     .exclude_similar()
+///     .generate()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct PasswordGenerator {
+    length: usize,
+    classes: Vec<CharClass>,
+    exclude_similar: bool,
+}
+impl PasswordGenerator {
+    /// Passwords of `length` characters. Until [`Self::include()`] is
+    /// called, all classes are used.
+    pub fn new(length: usize) -> Self {
+        Self {
+            length,
+            classes: Vec::new(),
+            exclude_similar: false,
+        }
+    }
+    pub fn length(&mut self, length: usize) -> &mut Self {
+        self.length = length;
+        self
+    }
+    /// Adds a character class.
+    pub fn include(&mut self, class: CharClass) -> &mut Self {
+        if !self.classes.contains(&class) {
+            self.classes.push(class);
+        }
+        self
+    }
+    /// Leaves out characters such as `0`, `O`, `1`, `l` and `I`, for
+    /// passwords that are read aloud or copied by hand.
+    pub fn exclude_similar(&mut self) -> &mut Self {
+        self.exclude_similar = true;
+        self
+    }
+    fn classes(&self) -> &[CharClass] {
+        match self.classes.is_empty() {
+            true => &[
+                CharClass::Lowercase,
+                CharClass::Uppercase,
+                CharClass::Digit,
+                CharClass::Symbol,
+            ],
+            false => &self.classes,
+        }
+    }
+    fn class_chars(&self, class: CharClass) -> impl Iterator<Item = u8> + '_ {
+        alphabet(class)
+            .iter()
+            .copied()
+            .filter(|c| !(self.exclude_similar && SIMILAR.contains(c)))
+    }
+    fn alphabet(&self) -> Vec<u8> {
+        self.classes()
+            .iter()
+            .flat_map(|class| self.class_chars(*class))
+            .collect()
+    }
+    /// Entropy of the generated passwords in bits, which is
+    /// `length * log2(alphabet size)`. Requiring every class makes the
+    /// actual figure slightly lower.
+    pub fn entropy_bits(&self) -> f64 {
+        self.length as f64 * (self.alphabet().len() as f64).log2()
+    }
+    pub fn generate(&self) -> io::Result<Password> {
+        let mut pw = Password::try_new()?;
+        self.generate_into(&mut pw)?;
+        Ok(pw)
+    }
+    /// Generates into an existing password, replacing its contents. Fails
+    /// if the length does not fit, or is too short to hold every class.
+    pub fn generate_into<const N: usize>(&self, pw: &mut Password<N>) -> io::Result<()> {
+        let alphabet = self.alphabet();
+        if self.length > N - 1 || self.length < self.classes().len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "password length must be between {} and {}",
+                    self.classes().len(),
+                    N - 1
+                ),
+            ));
+        }
+        loop {
+            pw.clear();
+            let buf = pw.as_mut_slice();
+            for slot in buf[..self.length].iter_mut() {
+                *slot = alphabet[random_below(alphabet.len())?];
+            }
+            let buf = &buf[..self.length];
+            let complete = self
+                .classes()
+                .iter()
+                .all(|class| self.class_chars(*class).any(|c| buf.contains(&c)));
+            if complete {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Generates diceware-style passphrases by picking random words from a
+/// wordlist. Example:
+///
+/// ```
+/// let gen = PassphraseGenerator::from_file("/usr/share/dict/eff_large_wordlist.txt")?;
+/// println!("{:.0} bits of entropy", gen.entropy_bits());
+/// let pw = gen.generate()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct PassphraseGenerator {
+    wordlist: Vec<String>,
+    words: usize,
+    separator: String,
+}
+impl PassphraseGenerator {
+    /// Reads a wordlist with one word per line. In the numbered diceware
+    /// format, such as `11111 abacus`, the last field of the line is used.
+    /// Empty lines and duplicates are skipped. Defaults to 6 words
+    /// separated by spaces.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut words = Vec::new();
+        for line in reader.lines() {
+            if let Some(word) = line?.split_whitespace().last() {
+                words.push(word.to_string());
+            }
+        }
+        Self::from_words(words)
+    }
+    /// Uses the given words. Fails if there are fewer than two distinct
+    /// words.
+    pub fn from_words(mut words: Vec<String>) -> io::Result<Self> {
+        words.sort();
+        words.dedup();
+        if words.len() < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "wordlist needs at least two distinct words",
+            ));
+        }
+        Ok(Self {
+            wordlist: words,
+            words: 6,
+            separator: " ".to_string(),
+        })
+    }
+    /// Number of words in a passphrase, at least one.
+    pub fn words(&mut self, words: usize) -> &mut Self {
+        self.words = words;
+        self
+    }
+    pub fn separator(&mut self, separator: impl Into<String>) -> &mut Self {
+        self.separator = separator.into();
+        self
+    }
+    /// Entropy of the generated passphrases in bits, which is
+    /// `words * log2(wordlist size)`.
+    pub fn entropy_bits(&self) -> f64 {
+        self.words as f64 * (self.wordlist.len() as f64).log2()
+    }
+    pub fn generate(&self) -> io::Result<Password> {
+        let mut pw = Password::try_new()?;
+        self.generate_into(&mut pw)?;
+        Ok(pw)
+    }
+    /// Generates into an existing password, replacing its contents. Fails
+    /// with an error of kind `InvalidInput` if the number of words is zero
+    /// or the passphrase does not fit.
+    pub fn generate_into<const N: usize>(&self, pw: &mut Password<N>) -> io::Result<()> {
+        pw.clear();
+        if self.words == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "passphrase needs at least one word",
+            ));
+        }
+        let buf = pw.as_mut_slice();
+        let mut len = 0;
+        for i in 0..self.words {
+            let word = &self.wordlist[random_below(self.wordlist.len())?];
+            let sep = if i == 0 { "" } else { self.separator.as_str() };
+            for part in [sep, word.as_str()] {
+                let Some(dest) = buf.get_mut(len..len + part.len()) else {
+                    pw.clear();
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "passphrase does not fit in the password buffer",
+                    ));
+                };
+                dest.copy_from_slice(part.as_bytes());
+                len += part.len();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns a uniformly distributed random number below `n`, which must not
+/// be zero, rejecting the values that would bias the result towards small
+/// numbers.
+fn random_below(n: usize) -> io::Result<usize> {
+    let n = n as u64;
+    // the largest multiple of n that fits, as a count of accepted values
+    let zone = u64::MAX - u64::MAX % n;
+    loop {
+        let mut bytes = [0u8; 8];
+        getrandom(&mut bytes)?;
+        let value = u64::from_ne_bytes(bytes);
+        if value < zone {
+            return Ok((value % n) as usize);
+        }
+    }
+}
+
+/// Fills `buf` with random bytes from the kernel.
+pub(crate) fn getrandom(buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = unsafe {
+            libc::getrandom(
+                buf[filled..].as_mut_ptr() as *mut c_void,
+                buf.len() - filled,
+                0,
+            )
+        };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        filled += n as usize;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(list: &[&str]) -> Vec<String> {
+        list.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn random_below_covers_the_range() {
+        assert_eq!(random_below(1).unwrap(), 0);
+        let mut seen = [0; 5];
+        for _ in 0..1000 {
+            seen[random_below(5).unwrap()] += 1;
+        }
+        // each value is expected 200 times
+        assert!(seen.iter().all(|n| (100..300).contains(n)), "{seen:?}");
+        let big = usize::MAX / 2 + 1;
+        assert!(random_below(big).unwrap() < big);
+    }
+
+    #[test]
+    fn password_classes() {
+        let mut gen = PasswordGenerator::new(4);
+        gen.include(CharClass::Digit)
+            .include(CharClass::Symbol)
+            .exclude_similar();
+        for _ in 0..50 {
+            let pw = gen.generate().unwrap();
+            let s = pw.as_str().unwrap();
+            assert_eq!(s.len(), 4);
+            assert!(s.chars().any(|c| c.is_ascii_digit()));
+            assert!(s.chars().any(|c| c.is_ascii_punctuation()));
+            assert!(!s.bytes().any(|c| SIMILAR.contains(&c)));
+        }
+        assert!(gen.length(1).generate().is_err());
+        let mut small = Password::<8>::sized();
+        assert!(gen.length(8).generate_into(&mut small).is_err());
+        gen.length(7).generate_into(&mut small).unwrap();
+        assert_eq!(small.len(), 7);
+    }
+
+    #[test]
+    fn passphrase() {
+        let mut gen = PassphraseGenerator::from_words(words(&["b", "a", "b"])).unwrap();
+        assert_eq!(gen.entropy_bits(), 6.0);
+        let pw = gen.words(3).separator("-").generate().unwrap();
+        let s = pw.as_str().unwrap();
+        assert_eq!(s.len(), 5);
+        assert!(s.split('-').all(|w| w == "a" || w == "b"), "{s}");
+
+        let e = gen.words(0).generate().err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        let mut small = Password::<4>::sized();
+        assert!(gen.words(2).generate_into(&mut small).is_ok());
+        assert!(gen.words(3).generate_into(&mut small).is_err());
+        assert!(small.is_empty());
+    }
+
+    #[test]
+    fn too_few_words() {
+        assert!(PassphraseGenerator::from_words(words(&["a", "a"])).is_err());
+        assert!(PassphraseGenerator::from_words(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! crypt(3) password hashes, as found in `/etc/shadow`. Needs libcrypt
+//! (libxcrypt on most current distributions).
+use super::{ct_eq, generate::getrandom, secure::SecureBuf, Password};
+use std::{
+    ffi::{c_char, c_int, c_ulong, c_void, CStr, CString},
+    io,
+};
+
+#[link(name = "crypt")]
+extern "C" {
+    fn crypt_r(phrase: *const c_char, setting: *const c_char, data: *mut c_void) -> *mut c_char;
+    fn crypt_gensalt_rn(
+        prefix: *const c_char,
+        count: c_ulong,
+        rbytes: *const c_char,
+        nrbytes: c_int,
+        output: *mut c_char,
+        output_size: c_int,
+    ) -> *mut c_char;
+}
+
+/// `sizeof(struct crypt_data)` in libxcrypt
+const CRYPT_DATA_LEN: usize = 32768;
+/// `CRYPT_GENSALT_OUTPUT_SIZE` in libxcrypt
+const GENSALT_OUTPUT_LEN: usize = 192;
+/// Random bytes used for a salt. This is enough for every method.
+const SALT_BYTES: usize = 16;
+
+/// Hashing methods for [`Password::hash()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashMethod {
+    /// SHA-512-crypt, `$6$`. Supported everywhere.
+    Sha512,
+    /// yescrypt, `$y$`. The default on current Debian, Fedora and Arch,
+    /// but only available with libxcrypt.
+    Yescrypt,
+}
+impl HashMethod {
+    fn prefix(&self) -> &'static CStr {
+        match self {
+            Self::Sha512 => c"$6$",
+            Self::Yescrypt => c"$y$",
+        }
+    }
+}
+
+impl<const N: usize> Password<N> {
+    /// Hashes the password with a random salt and the default cost of
+    /// `method`, and returns the hash in the crypt(3) format used by
+    /// `/etc/shadow`, `chpasswd -e` and cloud-init. Example:
+    ///
+    /// ```
+    /// use std::io::{stdin, stdout};
+    /// let mut t = Term::new(stdin(), stdout())?;
+    /// let pw = t.prompt_for_password("New password")?;
+    /// let hash = pw.hash(HashMethod::Yescrypt)?;
+    /// println!("{}:{hash}:19000:0:99999:7:::", "deploy");
+    /// ```
+    pub fn hash(&self, method: HashMethod) -> io::Result<String> {
+        self.hash_with_cost(method, 0)
+    }
+    /// Like [`Self::hash()`], with a method specific cost: the number of
+    /// rounds for SHA-512 (1000 to 999999999, default 5000), or the cost
+    /// factor for yescrypt (1 to 11, default 5). A cost of 0 selects the
+    /// default.
+    pub fn hash_with_cost(&self, method: HashMethod, cost: u64) -> io::Result<String> {
+        let mut rbytes = [0u8; SALT_BYTES];
+        getrandom(&mut rbytes)?;
+        let mut setting = [0 as c_char; GENSALT_OUTPUT_LEN];
+        let r = unsafe {
+            crypt_gensalt_rn(
+                method.prefix().as_ptr(),
+                cost as c_ulong,
+                rbytes.as_ptr() as *const c_char,
+                rbytes.len() as c_int,
+                setting.as_mut_ptr(),
+                setting.len() as c_int,
+            )
+        };
+        if r.is_null() {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot hash with {method:?} and cost {cost}: {e}"),
+            ));
+        }
+        let setting = unsafe { CStr::from_ptr(setting.as_ptr()) };
+        crypt(self, setting)
+    }
+    /// Checks the password against a crypt(3) hash, comparing in constant
+    /// time. Locked accounts, whose hash starts with `!` or `*`, never
+    /// match. Fails if the hashing method is not supported.
+    pub fn verify(&self, hash: &str) -> io::Result<bool> {
+        if hash.is_empty() || hash.starts_with(['!', '*']) {
+            return Ok(false);
+        }
+        let setting = CString::new(hash)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "hash contains a nul byte"))?;
+        let computed = crypt(self, &setting)?;
+        Ok(ct_eq(computed.as_bytes(), hash.as_bytes()))
+    }
+}
+
+/// Runs `crypt_r` with its scratch space in a [`SecureBuf`], since it holds
+/// intermediate state derived from the password.
+fn crypt<const N: usize>(pw: &Password<N>, setting: &CStr) -> io::Result<String> {
+    let mut data = SecureBuf::new(CRYPT_DATA_LEN)?;
+    let r = unsafe {
+        crypt_r(
+            pw.as_cstr().as_ptr(),
+            setting.as_ptr(),
+            data.as_mut_ptr() as *mut c_void,
+        )
+    };
+    // failures return null or a string starting with '*'
+    let hash = (!r.is_null()).then(|| unsafe { CStr::from_ptr(r) });
+    match hash.map(|h| h.to_bytes()) {
+        Some(h) if !h.starts_with(b"*") => Ok(String::from_utf8_lossy(h).into_owned()),
+        // the setting is left out, since for verify() it is the stored hash
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unsupported hashing method or invalid setting",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn password(s: &str) -> Password {
+        let mut pw = Password::new();
+        pw.read_line(&mut s.as_bytes()).unwrap();
+        pw
+    }
+
+    #[test]
+    fn hash_and_verify() {
+        let pw = password("correct horse");
+        let hash = pw.hash_with_cost(HashMethod::Sha512, 1000).unwrap();
+        assert!(hash.starts_with("$6$rounds=1000$"), "{hash}");
+        assert!(pw.verify(&hash).unwrap());
+        assert!(!password("correct horsf").verify(&hash).unwrap());
+        assert!(!password("").verify(&hash).unwrap());
+        // a new salt every time
+        assert_ne!(pw.hash_with_cost(HashMethod::Sha512, 1000).unwrap(), hash);
+        // locked accounts
+        assert!(!pw.verify(&format!("!{hash}")).unwrap());
+        assert!(!pw.verify("*").unwrap());
+        assert!(!pw.verify("").unwrap());
+    }
+
+    #[test]
+    fn unsupported_method() {
+        let pw = password("secret");
+        let stored = "$99$c2FsdA$aGFzaA";
+        let e = pw.verify(stored).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(!e.to_string().contains("c2FsdA"), "{e}");
+        let e = pw.verify("$6$salt\0").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,399 @@
+//! Client for GnuPG's pinentry programs, which speak the Assuan protocol
+//! over stdin and stdout.
+use super::Password;
+use std::{
+    ffi::{CStr, OsStr, OsString},
+    fmt,
+    io::{self, Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+/// Error code that pinentry returns when the user cancels, after masking
+/// out the error source bits.
+const GPG_ERR_CANCELED: u32 = 99;
+
+/// Reasons a [`Pinentry`] request failed.
+#[derive(Debug)]
+pub enum PinentryError {
+    /// Starting or talking to the program failed
+    Io(io::Error),
+    /// The user closed the dialog or pressed cancel
+    Cancelled,
+    /// The program answered a command with `ERR`
+    Assuan { code: u32, message: String },
+    /// The program sent something that is not valid Assuan
+    Protocol(String),
+}
+impl fmt::Display for PinentryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "pinentry: {e}"),
+            Self::Cancelled => write!(f, "pinentry: operation cancelled"),
+            Self::Assuan { code, message } => write!(f, "pinentry: error {code}: {message}"),
+            Self::Protocol(line) => write!(f, "pinentry: unexpected response {line:?}"),
+        }
+    }
+}
+impl std::error::Error for PinentryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for PinentryError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+impl From<PinentryError> for io::Error {
+    fn from(e: PinentryError) -> Self {
+        match e {
+            PinentryError::Io(e) => e,
+            PinentryError::Cancelled => io::Error::new(io::ErrorKind::Interrupted, e),
+            _ => io::Error::other(e),
+        }
+    }
+}
+
+/// Asks for a password with the user's pinentry program, the same dialog
+/// that gpg and ssh-agent use. Texts are set with the chainable methods,
+/// and [`Self::get_pin()`] runs the program. Example:
+///
+/// ```
+/// let pw = Pinentry::new()
+///     .title("Backup")
+///     .description("Enter the passphrase for the backup key")
+///     .prompt("Passphrase:")
+///     .get_pin()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pinentry {
+    program: OsString,
+    title: Option<String>,
+    description: Option<String>,
+    prompt: Option<String>,
+    error: Option<String>,
+    timeout: Option<u32>,
+}
+impl Default for Pinentry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Pinentry {
+    /// Uses the `pinentry` program found in `PATH`.
+    pub fn new() -> Self {
+        Self::with_program("pinentry")
+    }
+    pub fn with_program(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            program: program.as_ref().to_os_string(),
+            title: None,
+            description: None,
+            prompt: None,
+            error: None,
+            timeout: None,
+        }
+    }
+    /// Window title (`SETTITLE`).
+    pub fn title(&mut self, title: impl Into<String>) -> &mut Self {
+        self.title = Some(title.into());
+        self
+    }
+    /// Longer explanation shown above the input (`SETDESC`).
+    pub fn description(&mut self, description: impl Into<String>) -> &mut Self {
+        self.description = Some(description.into());
+        self
+    }
+    /// Label next to the input (`SETPROMPT`).
+    pub fn prompt(&mut self, prompt: impl Into<String>) -> &mut Self {
+        self.prompt = Some(prompt.into());
+        self
+    }
+    /// Error shown along with the dialog, such as after a wrong password
+    /// (`SETERROR`).
+    pub fn error(&mut self, error: impl Into<String>) -> &mut Self {
+        self.error = Some(error.into());
+        self
+    }
+    /// Gives up after `seconds`, which makes [`Self::get_pin()`] fail with
+    /// an `Assuan` error (`SETTIMEOUT`).
+    pub fn timeout(&mut self, seconds: u32) -> &mut Self {
+        self.timeout = Some(seconds);
+        self
+    }
+    /// Runs the program and asks for the password. The answer is decoded
+    /// straight into the [`Password`] buffer.
+    pub fn get_pin(&self) -> Result<Password, PinentryError> {
+        let mut conn = Connection::start(&self.program)?;
+        // the greeting
+        conn.read_response(None)?;
+        // tell a curses pinentry which terminal to use; failures only
+        // matter to curses pinentries, so they are ignored
+        if let Some(tty) = ttyname(0) {
+            _ = conn.command("OPTION", Some(&format!("ttyname={tty}")));
+            if let Ok(term) = std::env::var("TERM") {
+                _ = conn.command("OPTION", Some(&format!("ttytype={term}")));
+            }
+        }
+        let texts = [
+            ("SETTITLE", &self.title),
+            ("SETDESC", &self.description),
+            ("SETPROMPT", &self.prompt),
+            ("SETERROR", &self.error),
+        ];
+        for (command, text) in texts {
+            if let Some(text) = text {
+                conn.command(command, Some(text))?;
+            }
+        }
+        if let Some(seconds) = self.timeout {
+            conn.command("SETTIMEOUT", Some(&seconds.to_string()))?;
+        }
+        let mut pw = Password::try_new()?;
+        conn.send("GETPIN", None)?;
+        conn.read_response(Some(&mut pw))?;
+        _ = conn.send("BYE", None);
+        Ok(pw)
+    }
+}
+
+/// A running pinentry program.
+struct Connection {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+impl Connection {
+    fn start(program: &OsStr) -> io::Result<Self> {
+        let mut child = Command::new(program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("Should have piped stdin");
+        let stdout = child.stdout.take().expect("Should have piped stdout");
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+    /// Sends a command and waits for `OK`.
+    fn command(&mut self, command: &str, arg: Option<&str>) -> Result<(), PinentryError> {
+        self.send(command, arg)?;
+        self.read_response(None)
+    }
+    fn send(&mut self, command: &str, arg: Option<&str>) -> io::Result<()> {
+        let mut line = command.to_string();
+        if let Some(arg) = arg {
+            line.push(' ');
+            line.push_str(&escape(arg));
+        }
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.flush()
+    }
+    /// Reads lines up to `OK` or `ERR`. Data lines are decoded into `pw`,
+    /// or ignored if it is `None`. The output is read a byte at a time, so
+    /// that the password never passes through another buffer.
+    fn read_response(&mut self, mut pw: Option<&mut Password>) -> Result<(), PinentryError> {
+        loop {
+            let first = self.byte()?;
+            if first == b'\n' {
+                continue;
+            }
+            let second = self.byte()?;
+            if [first, second] == *b"D " {
+                match pw.as_deref_mut() {
+                    Some(pw) => self.read_data(pw)?,
+                    None => _ = self.rest_of_line()?,
+                }
+                continue;
+            }
+            let mut line = vec![first];
+            if second != b'\n' {
+                line.push(second);
+                line.extend(self.rest_of_line()?);
+            }
+            let line = String::from_utf8_lossy(&line).into_owned();
+            let (keyword, rest) = line.split_once(' ').unwrap_or((&line, ""));
+            match keyword {
+                "OK" => return Ok(()),
+                "ERR" => {
+                    let (code, message) = rest.split_once(' ').unwrap_or((rest, ""));
+                    let code: u32 = code
+                        .parse()
+                        .map_err(|_| PinentryError::Protocol(line.clone()))?;
+                    if code & 0xffff == GPG_ERR_CANCELED {
+                        return Err(PinentryError::Cancelled);
+                    }
+                    return Err(PinentryError::Assuan {
+                        code,
+                        message: message.to_string(),
+                    });
+                }
+                // we have nothing to add, such as a passphrase quality bar
+                "INQUIRE" => self.send("END", None)?,
+                // status lines and comments
+                "S" | "#" => (),
+                _ => return Err(PinentryError::Protocol(line)),
+            }
+        }
+    }
+    /// Decodes the percent escaped data of a `D` line into `pw`, appending
+    /// to what earlier lines added.
+    fn read_data(&mut self, pw: &mut Password) -> io::Result<()> {
+        let mut len = pw.len();
+        loop {
+            let mut b = self.byte()?;
+            if b == b'\n' {
+                return Ok(());
+            }
+            if b == b'%' {
+                let hi = self.byte()?;
+                let lo = self.byte()?;
+                b = hex_value(hi)
+                    .zip(hex_value(lo))
+                    .map(|(hi, lo)| hi << 4 | lo)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "invalid percent escape")
+                    })?;
+            }
+            match pw.as_mut_slice().get_mut(len) {
+                Some(slot) => *slot = b,
+                None => pw.set_truncated(),
+            }
+            len += 1;
+            b = 0;
+        }
+    }
+    fn rest_of_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::new();
+        loop {
+            match self.byte()? {
+                b'\n' => return Ok(line),
+                b => line.push(b),
+            }
+        }
+    }
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut b = [0u8; 1];
+        self.stdout.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+}
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // pinentry exits after BYE or when its input closes; kill it in case
+        // it is stuck, then reap it
+        _ = self.child.kill();
+        _ = self.child.wait();
+    }
+}
+
+/// Escapes the characters that Assuan does not allow in arguments.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '\r' => escaped.push_str("%0D"),
+            '\n' => escaped.push_str("%0A"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+fn ttyname(fd: libc::c_int) -> Option<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::ttyname_r(fd, buf.as_mut_ptr(), buf.len()) } != 0 {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+    };
+
+    /// Writes a fake pinentry that answers `OK` to everything except
+    /// `GETPIN`, which gets the `getpin` lines.
+    fn fake_pinentry(name: &str, getpin: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        let script = format!(
+            "#!/bin/sh\n\
+             echo 'OK Pleased to meet you'\n\
+             while read -r command rest; do\n\
+             \tcase $command in\n\
+             \tGETPIN) printf '%s\\n' {getpin} ;;\n\
+             \tBYE) echo OK; exit 0 ;;\n\
+             \t*) echo OK ;;\n\
+             \tesac\n\
+             done\n"
+        );
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+    fn get_pin(program: &Path) -> Result<Password, PinentryError> {
+        let mut pinentry = Pinentry::with_program(program);
+        pinentry.description("100% sure?\nreally").timeout(5);
+        // another test forking while the script was open for writing
+        // keeps it busy until that child execs
+        loop {
+            match pinentry.get_pin() {
+                Err(PinentryError::Io(e)) if e.raw_os_error() == Some(libc::ETXTBSY) => {
+                    std::thread::sleep(std::time::Duration::from_millis(10))
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[test]
+    fn percent_decoding() {
+        let path = fake_pinentry("fake-pinentry-ok", "'D s%25ecr%0Aet' OK");
+        let pw = get_pin(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pw.to_bytes(), b"s%ecr\net");
+        assert!(!pw.is_truncated());
+    }
+
+    #[test]
+    fn cancelled() {
+        let path = fake_pinentry("fake-pinentry-cancel", "'ERR 83886179 Operation cancelled'");
+        let result = get_pin(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(PinentryError::Cancelled)));
+    }
+
+    #[test]
+    fn other_errors() {
+        let path = fake_pinentry("fake-pinentry-timeout", "'ERR 83886142 Timeout'");
+        let result = get_pin(&path);
+        std::fs::remove_file(&path).unwrap();
+        match result {
+            Err(PinentryError::Assuan { code, message }) => {
+                assert_eq!((code, message.as_str()), (83886142, "Timeout"))
+            }
+            _ => panic!("Should fail with an Assuan error"),
+        }
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape("100% sure?\r\nreally"), "100%25 sure?%0D%0Areally");
+    }
+}
@@ -0,0 +1,307 @@
+//! Rules for choosing a new password, and the prompt that enforces them.
+use super::Password;
+use crate::tty::Term;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+/// Character classes that a [`PasswordPolicy`] may require.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    /// ASCII punctuation, such as `!`, `#` or `~`
+    Symbol,
+}
+impl CharClass {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Self::Lowercase => c.is_lowercase(),
+            Self::Uppercase => c.is_uppercase(),
+            Self::Digit => c.is_ascii_digit(),
+            Self::Symbol => c.is_ascii_punctuation(),
+        }
+    }
+}
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lowercase => write!(f, "a lowercase letter"),
+            Self::Uppercase => write!(f, "an uppercase letter"),
+            Self::Digit => write!(f, "a digit"),
+            Self::Symbol => write!(f, "a symbol"),
+        }
+    }
+}
+
+/// Reasons a new password was rejected. The `Display` implementation is
+/// meant to be shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    TooShort {
+        min: usize,
+    },
+    /// The input did not fit in the buffer, which holds `max` bytes
+    TooLong {
+        max: usize,
+    },
+    MissingClass(CharClass),
+    NotUtf8,
+    SameAsUsername,
+    InWordlist,
+    /// The confirmation did not match the first entry
+    Mismatch,
+}
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort { min } => {
+                write!(f, "The password must be at least {min} characters long")
+            }
+            Self::TooLong { max } => {
+                write!(f, "The password must be at most {max} bytes long")
+            }
+            Self::MissingClass(class) => write!(f, "The password must contain {class}"),
+            Self::NotUtf8 => write!(f, "The password is not valid UTF-8"),
+            Self::SameAsUsername => write!(f, "The password must not be the user name"),
+            Self::InWordlist => write!(f, "The password is too common"),
+            Self::Mismatch => write!(f, "The passwords do not match"),
+        }
+    }
+}
+impl std::error::Error for PolicyViolation {}
+
+/// Requirements for a new password, checked by
+/// [`Term::prompt_for_new_password()`] or directly with [`Self::check()`].
+/// The default policy accepts any non-empty password.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    classes: Vec<CharClass>,
+    username: Option<String>,
+    wordlist: Option<PathBuf>,
+}
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl PasswordPolicy {
+    pub fn new() -> Self {
+        Self {
+            min_length: 1,
+            classes: Vec::new(),
+            username: None,
+            wordlist: None,
+        }
+    }
+    /// Minimum length in characters.
+    pub fn min_length(&mut self, chars: usize) -> &mut Self {
+        self.min_length = chars;
+        self
+    }
+    /// Requires at least one character of the given class.
+    pub fn require(&mut self, class: CharClass) -> &mut Self {
+        if !self.classes.contains(&class) {
+            self.classes.push(class);
+        }
+        self
+    }
+    /// Rejects the user name as a password, ignoring ASCII case.
+    pub fn not_username(&mut self, username: impl Into<String>) -> &mut Self {
+        self.username = Some(username.into());
+        self
+    }
+    /// Rejects passwords that appear in a file with one word per line,
+    /// ignoring ASCII case. The file is read on every check.
+    pub fn wordlist(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.wordlist = Some(path.as_ref().to_path_buf());
+        self
+    }
+    /// Checks a password against the policy. A password that was
+    /// truncated on input is rejected, since it is not what the user
+    /// typed. The outer `Result` fails only if the wordlist cannot be
+    /// read.
+    pub fn check<const N: usize>(&self, pw: &Password<N>) -> io::Result<Result<(), PolicyViolation>> {
+        if pw.is_truncated() {
+            return Ok(Err(PolicyViolation::TooLong { max: N - 1 }));
+        }
+        let Ok(s) = pw.as_str() else {
+            return Ok(Err(PolicyViolation::NotUtf8));
+        };
+        if s.chars().count() < self.min_length.max(1) {
+            return Ok(Err(PolicyViolation::TooShort {
+                min: self.min_length.max(1),
+            }));
+        }
+        for class in self.classes.iter() {
+            if !s.chars().any(|c| class.matches(c)) {
+                return Ok(Err(PolicyViolation::MissingClass(*class)));
+            }
+        }
+        if let Some(name) = self.username.as_ref() {
+            if s.eq_ignore_ascii_case(name) {
+                return Ok(Err(PolicyViolation::SameAsUsername));
+            }
+        }
+        if let Some(path) = self.wordlist.as_ref() {
+            let reader = BufReader::new(File::open(path)?);
+            for line in reader.split(b'\n') {
+                let line = line?;
+                let word = line.strip_suffix(b"\r").unwrap_or(&line);
+                if word.eq_ignore_ascii_case(pw.to_bytes()) {
+                    return Ok(Err(PolicyViolation::InWordlist));
+                }
+            }
+        }
+        Ok(Ok(()))
+    }
+}
+
+impl<I: Read, O: AsRawFd + Write> Term<I, O> {
+    /// Prompts for a new password twice, checks that the entries match and
+    /// that the password satisfies `policy`. On failure, the reason is
+    /// printed and the user is asked again, up to `max_attempts` times,
+    /// after which an error of kind `PermissionDenied` is returned. The
+    /// entries are compared in constant time. Example:
+    ///
+    /// ```
+    /// use std::io::{stdin, stdout};
+    /// let mut t = Term::new(stdin(), stdout())?;
+    /// let pw = t.prompt_for_new_password(
+    ///     "New password",
+    ///     "Retype new password",
+    ///     PasswordPolicy::new()
+    ///         .min_length(12)
+    ///         .require(CharClass::Digit)
+    ///         .not_username("admin")
+    ///         .wordlist("/usr/share/dict/words"),
+    ///     3,
+    /// )?;
+    /// ```
+    pub fn prompt_for_new_password(
+        &mut self,
+        prompt: impl fmt::Display,
+        confirm_prompt: impl fmt::Display,
+        policy: &PasswordPolicy,
+        max_attempts: usize,
+    ) -> io::Result<Password> {
+        for _ in 0..max_attempts {
+            let pw = self.prompt_for_password(&prompt)?;
+            if let Err(violation) = policy.check(&pw)? {
+                writeln!(self, "{violation}.")?;
+                continue;
+            }
+            let confirm = self.prompt_for_password(&confirm_prompt)?;
+            if pw != confirm {
+                writeln!(self, "{}.", PolicyViolation::Mismatch)?;
+                continue;
+            }
+            return Ok(pw);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "too many failed attempts to choose a password",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn password(s: &str) -> Password<16> {
+        let mut pw = Password::sized();
+        pw.read_line(&mut format!("{s}\n").as_bytes()).unwrap();
+        pw
+    }
+    fn check(policy: &PasswordPolicy, s: &str) -> Result<(), PolicyViolation> {
+        policy.check(&password(s)).unwrap()
+    }
+
+    #[test]
+    fn length() {
+        let policy = PasswordPolicy::new();
+        assert_eq!(
+            check(&policy, ""),
+            Err(PolicyViolation::TooShort { min: 1 })
+        );
+        assert_eq!(check(&policy, "x"), Ok(()));
+        let policy = PasswordPolicy::new().min_length(4).clone();
+        assert_eq!(
+            check(&policy, "\u{e9}\u{e9}\u{e9}"),
+            Err(PolicyViolation::TooShort { min: 4 })
+        );
+        assert_eq!(check(&policy, "\u{e9}\u{e9}\u{e9}\u{e9}"), Ok(()));
+        assert_eq!(
+            check(&policy, "0123456789abcdefg"),
+            Err(PolicyViolation::TooLong { max: 15 })
+        );
+    }
+
+    #[test]
+    fn classes() {
+        let policy = PasswordPolicy::new()
+            .require(CharClass::Lowercase)
+            .require(CharClass::Uppercase)
+            .require(CharClass::Digit)
+            .require(CharClass::Symbol)
+            .clone();
+        assert_eq!(check(&policy, "aB3!"), Ok(()));
+        assert_eq!(
+            check(&policy, "AB3!"),
+            Err(PolicyViolation::MissingClass(CharClass::Lowercase))
+        );
+        assert_eq!(
+            check(&policy, "ab3!"),
+            Err(PolicyViolation::MissingClass(CharClass::Uppercase))
+        );
+        assert_eq!(
+            check(&policy, "aB!!"),
+            Err(PolicyViolation::MissingClass(CharClass::Digit))
+        );
+        assert_eq!(
+            check(&policy, "aB34"),
+            Err(PolicyViolation::MissingClass(CharClass::Symbol))
+        );
+        // neither spaces nor non-ASCII characters count as symbols
+        assert_eq!(
+            check(&policy, "aB3 \u{e9}\u{20ac}"),
+            Err(PolicyViolation::MissingClass(CharClass::Symbol))
+        );
+    }
+
+    #[test]
+    fn username_and_wordlist() {
+        let path = std::env::temp_dir().join(format!("policy-words-{}", std::process::id()));
+        std::fs::write(&path, "password\r\nletmein\n").unwrap();
+        let policy = PasswordPolicy::new()
+            .not_username("Admin")
+            .wordlist(&path)
+            .clone();
+        assert_eq!(
+            check(&policy, "aDMIN"),
+            Err(PolicyViolation::SameAsUsername)
+        );
+        assert_eq!(check(&policy, "PassWord"), Err(PolicyViolation::InWordlist));
+        assert_eq!(check(&policy, "letmein"), Err(PolicyViolation::InWordlist));
+        assert_eq!(check(&policy, "letmein2"), Ok(()));
+        std::fs::remove_file(&path).unwrap();
+        assert!(policy.check(&password("x")).is_err());
+    }
+
+    #[test]
+    fn not_utf8() {
+        let mut pw = Password::<8>::sized();
+        pw.read_line(&mut &b"\xff\n"[..]).unwrap();
+        assert_eq!(
+            PasswordPolicy::new().check(&pw).unwrap(),
+            Err(PolicyViolation::NotUtf8)
+        );
+    }
+}
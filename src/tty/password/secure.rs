@@ -0,0 +1,177 @@
+//! Page-aligned buffer for secrets that is kept out of swap and core
+//! dumps.
+use std::{
+    io,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+/// A fixed-size, zero-initialized buffer in its own anonymous mapping.
+///
+/// The pages are locked into memory with `mlock` when `RLIMIT_MEMLOCK`
+/// allows it, excluded from core dumps with `MADV_DONTDUMP`, and wiped in
+/// child processes after `fork` with `MADV_WIPEONFORK`. The buffer is
+/// zeroed before it is unmapped.
+///
+/// The buffer may be made inaccessible with [`Self::protect()`]. Accessing
+/// a protected buffer panics instead of faulting.
+pub(crate) struct SecureBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+    map_len: usize,
+    locked: bool,
+    protected: bool,
+}
+// SAFETY: the buffer is uniquely owned, like a Box<[u8]>
+unsafe impl Send for SecureBuf {}
+unsafe impl Sync for SecureBuf {}
+
+impl SecureBuf {
+    /// Maps a buffer of `len` bytes. Fails if the memory cannot be
+    /// mapped, as under a low `RLIMIT_AS`.
+    pub fn new(len: usize) -> io::Result<Self> {
+        let page = page_size();
+        let map_len = len
+            .max(1)
+            .div_ceil(page)
+            .checked_mul(page)
+            .ok_or(io::ErrorKind::OutOfMemory)?;
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // these are best effort: mlock fails when RLIMIT_MEMLOCK is too low,
+        // and the madvise flags need Linux 3.4 and 4.14 respectively
+        let locked = unsafe { libc::mlock(ptr, map_len) } == 0;
+        unsafe {
+            libc::madvise(ptr, map_len, libc::MADV_DONTDUMP);
+            libc::madvise(ptr, map_len, libc::MADV_WIPEONFORK);
+        }
+        Ok(Self {
+            ptr: NonNull::new(ptr as *mut u8).expect("mmap should not return null"),
+            len,
+            map_len,
+            locked,
+            protected: false,
+        })
+    }
+    /// Returns true if the buffer is locked into memory and cannot be
+    /// swapped out.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+    /// Returns true if the buffer is currently inaccessible.
+    pub fn is_protected(&self) -> bool {
+        self.protected
+    }
+    /// Makes the buffer inaccessible (`PROT_NONE`), so that stray reads,
+    /// for example through a memory disclosure bug, fault instead of
+    /// returning the secret.
+    pub fn protect(&mut self) -> io::Result<()> {
+        self.mprotect(libc::PROT_NONE)?;
+        self.protected = true;
+        Ok(())
+    }
+    /// Makes the buffer accessible again.
+    pub fn unprotect(&mut self) -> io::Result<()> {
+        self.mprotect(libc::PROT_READ | libc::PROT_WRITE)?;
+        self.protected = false;
+        Ok(())
+    }
+    fn mprotect(&self, prot: libc::c_int) -> io::Result<()> {
+        let r =
+            unsafe { libc::mprotect(self.ptr.as_ptr() as *mut libc::c_void, self.map_len, prot) };
+        if r != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    fn check_access(&self) {
+        assert!(
+            !self.protected,
+            "secret buffer is protected; unprotect it before access"
+        );
+    }
+}
+impl Deref for SecureBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.check_access();
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+impl DerefMut for SecureBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.check_access();
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+impl Drop for SecureBuf {
+    fn drop(&mut self) {
+        if self.protected {
+            // if this fails, the pages are unmapped without being zeroed,
+            // which is still better than leaking them
+            _ = self.unprotect();
+        }
+        if !self.protected {
+            self.fill(0);
+            // ensure that the compiler and the hardware cannot defer the fill
+            use std::sync::atomic::{self, Ordering};
+            atomic::compiler_fence(Ordering::SeqCst);
+            atomic::fence(Ordering::SeqCst);
+        }
+        let ptr = self.ptr.as_ptr() as *mut libc::c_void;
+        unsafe {
+            if self.locked {
+                libc::munlock(ptr, self.map_len);
+            }
+            libc::munmap(ptr, self.map_len);
+        }
+    }
+}
+
+fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        n if n > 0 => n as usize,
+        _ => 4096,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new() {
+        let buf = SecureBuf::new(10).unwrap();
+        assert_eq!(&buf[..], [0; 10]);
+        assert_eq!(buf.map_len, page_size());
+        assert!(SecureBuf::new(1 << 60).is_err());
+        assert!(SecureBuf::new(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn protect() {
+        let mut buf = SecureBuf::new(4).unwrap();
+        buf.copy_from_slice(b"abcd");
+        buf.protect().unwrap();
+        assert!(buf.is_protected());
+        let read = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| buf[0]));
+        assert!(read.is_err());
+        buf.unprotect().unwrap();
+        assert!(!buf.is_protected());
+        assert_eq!(&buf[..], b"abcd");
+        // dropping a protected buffer unprotects it to zero it
+        buf.protect().unwrap();
+    }
+}
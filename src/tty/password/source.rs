@@ -0,0 +1,193 @@
+//! Reading passwords non-interactively, for scripts and CI.
+use super::Password;
+use crate::tty::{isatty, Term};
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, stdin, Read, Write},
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::{ffi::OsStringExt, fs::MetadataExt},
+    },
+    path::{Path, PathBuf},
+};
+
+/// Where to read a password from when it is not typed in. Usually built
+/// from command line options such as `--password-file`, `--password-fd`
+/// or `--password-env`, and passed to [`read_password()`].
+#[derive(Debug)]
+pub enum PasswordSource {
+    /// See [`Password::from_file()`]
+    File(PathBuf),
+    /// See [`Password::from_fd()`]
+    Fd(OwnedFd),
+    /// See [`Password::from_env()`]
+    Env(OsString),
+}
+impl PasswordSource {
+    pub fn read(self) -> io::Result<Password> {
+        match self {
+            Self::File(path) => Password::from_file(path),
+            Self::Fd(fd) => Password::from_fd(fd),
+            Self::Env(name) => Password::from_env(name),
+        }
+    }
+}
+
+impl Password {
+    /// Reads the first line of a file. The file must not be readable or
+    /// writable by group or others (mode 0600 or stricter), otherwise an
+    /// error of kind `PermissionDenied` is returned without reading it.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut file = File::open(path)?;
+        let mode = file.metadata()?.mode();
+        if mode & 0o077 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{} has mode {:o}, but password files must only be accessible by their owner (0600)",
+                    path.display(),
+                    mode & 0o777
+                ),
+            ));
+        }
+        let mut pw = Self::new();
+        pw.read_line(&mut file)?;
+        Ok(pw)
+    }
+    /// Reads one line from a file descriptor, such as one inherited for a
+    /// `--password-fd 3` option, then closes the descriptor. Example:
+    ///
+    /// ```
+    /// // run as: echo hunter2 | tool --password-fd 3 3<&0
+    /// // SAFETY: the shell opened fd 3 for us, and nothing else uses it
+    /// let fd = unsafe { OwnedFd::from_raw_fd(3) };
+    /// let pw = Password::from_fd(fd)?;
+    /// ```
+    pub fn from_fd(fd: impl Into<OwnedFd>) -> io::Result<Self> {
+        let mut file = File::from(fd.into());
+        let mut pw = Self::new();
+        pw.read_line(&mut file)?;
+        Ok(pw)
+    }
+    /// Takes the password from an environment variable, and removes the
+    /// variable so that child processes do not inherit it. Fails with an
+    /// error of kind `NotFound` if it is not set.
+    ///
+    /// Note that the initial environment of a process stays readable in
+    /// `/proc/<pid>/environ` by the same user, so prefer the other sources
+    /// where possible.
+    pub fn from_env(name: impl AsRef<std::ffi::OsStr>) -> io::Result<Self> {
+        let name = name.as_ref();
+        let Some(value) = std::env::var_os(name) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("environment variable {} is not set", name.to_string_lossy()),
+            ));
+        };
+        std::env::remove_var(name);
+        let mut value = value.into_vec();
+        let mut pw = Self::new();
+        let buf = pw.as_mut_slice();
+        let len = value.len().min(buf.len());
+        buf[..len].copy_from_slice(&value[..len]);
+        if len < value.len() {
+            pw.set_truncated();
+        }
+        value.fill(0);
+        Ok(pw)
+    }
+}
+
+/// Gets a password from `source` if one is given. Otherwise prompts for
+/// it on the terminal if the standard input is one, writing the prompt to
+/// `/dev/tty` so that it is shown even when the output is redirected, or
+/// reads a line from the standard input if it is not, so that
+/// `echo hunter2 | tool` works. Unlike [`Term::read_password()`], this
+/// needs no [`Term`], which cannot be made when the standard output is
+/// not a terminal. Example:
+///
+/// ```
+/// let source = args.password_file.map(PasswordSource::File);
+/// let pw = read_password("Enter the password", source)?;
+/// ```
+pub fn read_password(
+    prompt: impl fmt::Display,
+    source: Option<PasswordSource>,
+) -> io::Result<Password> {
+    if let Some(source) = source {
+        return source.read();
+    }
+    let input = stdin();
+    if isatty(input.as_raw_fd()) {
+        let tty = OpenOptions::new().write(true).open("/dev/tty")?;
+        return Term::new(input, tty)?.prompt_for_password(prompt);
+    }
+    let mut pw = Password::try_new()?;
+    pw.read_line(&mut input.lock())?;
+    Ok(pw)
+}
+
+impl<I: Read + AsRawFd, O: AsRawFd + Write> Term<I, O> {
+    /// Gets a password from `source` if one is given. Otherwise prompts for
+    /// it with [`Self::prompt_for_password()`] if both the input and the
+    /// output are terminals, or reads a line from the input if it is not,
+    /// so that `echo hunter2 | tool` works. Example:
+    ///
+    /// ```
+    /// use std::io::{stdin, stdout};
+    /// let source = args.password_file.map(PasswordSource::File);
+    /// let mut t = Term::new(stdin(), stdout())?;
+    /// let pw = t.read_password("Enter the password", source)?;
+    /// ```
+    pub fn read_password(
+        &mut self,
+        prompt: impl fmt::Display,
+        source: Option<PasswordSource>,
+    ) -> io::Result<Password> {
+        if let Some(source) = source {
+            return source.read();
+        }
+        if self.is_a_tty() && isatty(self.input().as_raw_fd()) {
+            return self.prompt_for_password(prompt);
+        }
+        let mut pw = Password::try_new()?;
+        pw.read_line(self)?;
+        Ok(pw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn sources() {
+        let (reader, writer) = nix::unistd::pipe().unwrap();
+        File::from(writer).write_all(b"from pipe\n").unwrap();
+        let pw = PasswordSource::Fd(reader).read().unwrap();
+        assert_eq!(pw.as_str(), Ok("from pipe"));
+
+        std::env::set_var("SOURCE_TEST_PASSWORD", "from env");
+        let pw = PasswordSource::Env("SOURCE_TEST_PASSWORD".into())
+            .read()
+            .unwrap();
+        assert_eq!(pw.as_str(), Ok("from env"));
+        assert!(std::env::var_os("SOURCE_TEST_PASSWORD").is_none());
+        let e = Password::from_env("SOURCE_TEST_PASSWORD").err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+
+        let path = std::env::temp_dir().join(format!("source-test-{}", std::process::id()));
+        std::fs::write(&path, "from file\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let e = Password::from_file(&path).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let pw = PasswordSource::File(path.clone()).read().unwrap();
+        assert_eq!(pw.as_str(), Ok("from file"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
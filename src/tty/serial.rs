@@ -0,0 +1,397 @@
+//! Serial port configuration and modem control lines.
+use super::{
+    flags::{ControlFlags, InputFlags, LocalFlags, OutputFlags},
+    io_result, Term,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::Path,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+    /// Parity bit always 1
+    Mark,
+    /// Parity bit always 0
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// RTS/CTS
+    Hardware,
+    /// XON/XOFF
+    Software,
+}
+
+/// Line settings for a serial port, applied with
+/// [`Term::configure_serial()`]. Defaults to 9600 baud, 8 data bits, no
+/// parity, one stop bit (8N1) and no flow control.
+#[derive(Debug, Clone)]
+pub struct SerialConfig {
+    baud: u32,
+    data_bits: u8,
+    parity: Parity,
+    stop_bits: StopBits,
+    flow_control: FlowControl,
+}
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self::new(9600)
+    }
+}
+impl SerialConfig {
+    /// 8N1 at the given baud rate, without flow control.
+    pub fn new(baud: u32) -> Self {
+        Self {
+            baud,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+        }
+    }
+    pub fn baud(&mut self, baud: u32) -> &mut Self {
+        self.baud = baud;
+        self
+    }
+    /// Data bits per character, 5 to 8.
+    pub fn data_bits(&mut self, bits: u8) -> &mut Self {
+        self.data_bits = bits;
+        self
+    }
+    pub fn parity(&mut self, parity: Parity) -> &mut Self {
+        self.parity = parity;
+        self
+    }
+    pub fn stop_bits(&mut self, stop_bits: StopBits) -> &mut Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+    pub fn flow_control(&mut self, flow_control: FlowControl) -> &mut Self {
+        self.flow_control = flow_control;
+        self
+    }
+}
+
+/// State of the modem control lines, as returned by
+/// [`Term::modem_lines()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModemLines {
+    /// Data Terminal Ready (output)
+    pub dtr: bool,
+    /// Request To Send (output)
+    pub rts: bool,
+    /// Clear To Send (input)
+    pub cts: bool,
+    /// Data Set Ready (input)
+    pub dsr: bool,
+    /// Data Carrier Detect (input)
+    pub dcd: bool,
+    /// Ring Indicator (input)
+    pub ri: bool,
+}
+
+impl Term<File, File> {
+    /// Opens a serial device such as `/dev/ttyUSB0` for reading and
+    /// writing, and sets exclusive mode (`TIOCEXCL`) so that other
+    /// programs cannot open it while it is in use. The device does not
+    /// become the controlling terminal, and the open does not wait for a
+    /// carrier. The settings at open time are what [`Self::reset()`]
+    /// restores. Example:
+    ///
+    /// ```
+    /// use std::io::Write;
+    /// let mut port = Term::open_serial("/dev/ttyUSB0")?;
+    /// port.configure_serial(SerialConfig::new(115200).parity(Parity::Even))?
+    ///     .set(SetAction::TCSANOW)?;
+    /// port.set_dtr(false)?; // hold the board in reset
+    /// port.set_dtr(true)?;
+    /// port.write_all(b"help\r")?;
+    /// ```
+    pub fn open_serial(path: impl AsRef<Path>) -> io::Result<Self> {
+        // O_NONBLOCK keeps open() from waiting for carrier detect
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)?;
+        let fd = file.as_raw_fd();
+        io_result(unsafe { libc::ioctl(fd, libc::TIOCEXCL) })?;
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        io_result(unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) })?;
+        Self::new(file.try_clone()?, file)
+    }
+}
+
+impl<I, O: AsRawFd> Term<I, O> {
+    /// Sets up the working copy of the settings for binary serial
+    /// communication: raw mode without any input or output processing,
+    /// the receiver enabled, modem control lines ignored for reads, and
+    /// the line settings from `config`. Call [`Self::set()`] to apply.
+    pub fn configure_serial(&mut self, config: &SerialConfig) -> io::Result<&mut Self> {
+        self.with_flags(|b| {
+            b.disable(
+                InputFlags::IGNBRK
+                    | InputFlags::BRKINT
+                    | InputFlags::PARMRK
+                    | InputFlags::ISTRIP
+                    | InputFlags::INLCR
+                    | InputFlags::IGNCR
+                    | InputFlags::ICRNL
+                    | InputFlags::IXANY,
+            )
+            .disable(OutputFlags::OPOST)
+            .disable(
+                LocalFlags::ECHO
+                    | LocalFlags::ECHONL
+                    | LocalFlags::ICANON
+                    | LocalFlags::ISIG
+                    | LocalFlags::IEXTEN,
+            )
+            .enable(ControlFlags::CREAD | ControlFlags::CLOCAL)
+            .baud(config.baud)
+            .char_size(config.data_bits)
+            .read_min(1)
+            .read_timeout(Duration::ZERO);
+            match config.parity {
+                Parity::None => {
+                    b.disable(ControlFlags::PARENB | ControlFlags::PARODD | ControlFlags::CMSPAR)
+                }
+                Parity::Even => b
+                    .enable(ControlFlags::PARENB)
+                    .disable(ControlFlags::PARODD | ControlFlags::CMSPAR),
+                Parity::Odd => b
+                    .enable(ControlFlags::PARENB | ControlFlags::PARODD)
+                    .disable(ControlFlags::CMSPAR),
+                Parity::Mark => {
+                    b.enable(ControlFlags::PARENB | ControlFlags::PARODD | ControlFlags::CMSPAR)
+                }
+                Parity::Space => b
+                    .enable(ControlFlags::PARENB | ControlFlags::CMSPAR)
+                    .disable(ControlFlags::PARODD),
+            };
+            match config.parity {
+                Parity::None => b.disable(InputFlags::INPCK),
+                _ => b.enable(InputFlags::INPCK),
+            };
+            match config.stop_bits {
+                StopBits::One => b.disable(ControlFlags::CSTOPB),
+                StopBits::Two => b.enable(ControlFlags::CSTOPB),
+            };
+            match config.flow_control {
+                FlowControl::None => b
+                    .disable(ControlFlags::CRTSCTS)
+                    .disable(InputFlags::IXON | InputFlags::IXOFF),
+                FlowControl::Hardware => b
+                    .enable(ControlFlags::CRTSCTS)
+                    .disable(InputFlags::IXON | InputFlags::IXOFF),
+                FlowControl::Software => b
+                    .disable(ControlFlags::CRTSCTS)
+                    .enable(InputFlags::IXON | InputFlags::IXOFF),
+            };
+        })
+    }
+    /// Turns exclusive mode (`TIOCEXCL`) on or off. While it is on, further
+    /// opens of the device fail with `EBUSY`, except for root.
+    pub fn set_exclusive(&self, exclusive: bool) -> io::Result<()> {
+        let request = if exclusive {
+            libc::TIOCEXCL
+        } else {
+            libc::TIOCNXCL
+        };
+        io_result(unsafe { libc::ioctl(self.fd_out.as_raw_fd(), request) })
+    }
+    /// Reads the modem control lines (`TIOCMGET`).
+    pub fn modem_lines(&self) -> io::Result<ModemLines> {
+        let mut bits: libc::c_int = 0;
+        io_result(unsafe { libc::ioctl(self.fd_out.as_raw_fd(), libc::TIOCMGET, &mut bits) })?;
+        Ok(ModemLines {
+            dtr: bits & libc::TIOCM_DTR != 0,
+            rts: bits & libc::TIOCM_RTS != 0,
+            cts: bits & libc::TIOCM_CTS != 0,
+            dsr: bits & libc::TIOCM_DSR != 0,
+            dcd: bits & libc::TIOCM_CAR != 0,
+            ri: bits & libc::TIOCM_RNG != 0,
+        })
+    }
+    /// Raises or lowers the DTR line. Many development boards wire DTR to
+    /// their reset pin.
+    pub fn set_dtr(&self, on: bool) -> io::Result<()> {
+        self.set_modem_line(libc::TIOCM_DTR, on)
+    }
+    /// Raises or lowers the RTS line. Not useful while hardware flow
+    /// control is enabled, since the driver manages RTS itself.
+    pub fn set_rts(&self, on: bool) -> io::Result<()> {
+        self.set_modem_line(libc::TIOCM_RTS, on)
+    }
+    fn set_modem_line(&self, line: libc::c_int, on: bool) -> io::Result<()> {
+        let request = if on { libc::TIOCMBIS } else { libc::TIOCMBIC };
+        io_result(unsafe { libc::ioctl(self.fd_out.as_raw_fd(), request, &line) })
+    }
+    /// Sends a break condition for about `duration` (`TIOCSBRK` and
+    /// `TIOCCBRK`).
+    pub fn send_break(&self, duration: Duration) -> io::Result<()> {
+        let fd = self.fd_out.as_raw_fd();
+        io_result(unsafe { libc::ioctl(fd, libc::TIOCSBRK) })?;
+        std::thread::sleep(duration);
+        io_result(unsafe { libc::ioctl(fd, libc::TIOCCBRK) })
+    }
+    /// Waits until all output has been transmitted (`tcdrain`).
+    pub fn drain(&self) -> io::Result<()> {
+        io_result(unsafe { libc::tcdrain(self.fd_out.as_raw_fd()) })
+    }
+    /// Discards data that has been received but not read, and data that
+    /// has been written but not transmitted (`tcflush` with `TCIOFLUSH`).
+    pub fn discard_buffers(&self) -> io::Result<()> {
+        io_result(unsafe { libc::tcflush(self.fd_out.as_raw_fd(), libc::TCIOFLUSH) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tty::{openpty, settings::FlagGroup, SetAction};
+    use std::{
+        io::{Read, Write},
+        os::fd::AsFd,
+        path::PathBuf,
+    };
+
+    fn pty_path(slave: &File) -> PathBuf {
+        std::fs::read_link(format!("/proc/self/fd/{}", slave.as_raw_fd())).unwrap()
+    }
+    /// Waits up to `ms` for `fd` to become readable.
+    fn readable(fd: impl AsFd, ms: i32) -> bool {
+        let mut p = libc::pollfd {
+            fd: fd.as_fd().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut p, 1, ms) == 1 }
+    }
+
+    #[test]
+    fn configure_serial() {
+        let (_master, slave) = openpty().unwrap();
+        let mut port = Term::new((), slave).unwrap();
+        port.configure_serial(
+            SerialConfig::new(115200)
+                .data_bits(7)
+                .parity(Parity::Even)
+                .stop_bits(StopBits::Two)
+                .flow_control(FlowControl::Hardware),
+        )
+        .unwrap();
+        let t = port.working_termios();
+        assert_eq!(t.output_baud(), Some(115200));
+        assert_eq!(t.char_size(), 7);
+        let c = libc::PARENB | libc::CSTOPB | libc::CRTSCTS | libc::CREAD | libc::CLOCAL;
+        assert!(t.is_set(FlagGroup::Control, c));
+        assert!(!t.is_set(FlagGroup::Control, libc::PARODD));
+        assert!(t.is_set(FlagGroup::Input, libc::INPCK));
+        assert!(!t.is_set(FlagGroup::Input, libc::IXON));
+        assert!(!t.is_set(FlagGroup::Input, libc::ICRNL));
+        assert!(!t.is_set(FlagGroup::Output, libc::OPOST));
+        assert!(!t.is_set(FlagGroup::Local, libc::ICANON));
+        assert!(!t.is_set(FlagGroup::Local, libc::ECHO));
+        assert_eq!(t.control_char(libc::VMIN), 1);
+        assert_eq!(t.control_char(libc::VTIME), 0);
+
+        // a pty always reports 8 data bits without parity, but keeps the rest
+        port.set(SetAction::TCSANOW).unwrap();
+        let t = port.current_termios().unwrap();
+        assert_eq!(t.output_baud(), Some(115200));
+        assert!(t.is_set(FlagGroup::Control, libc::CSTOPB | libc::CRTSCTS));
+        assert!(!t.is_set(FlagGroup::Local, libc::ICANON));
+        assert_eq!(t.control_char(libc::VMIN), 1);
+
+        port.configure_serial(
+            SerialConfig::new(9600)
+                .parity(Parity::Odd)
+                .flow_control(FlowControl::Software),
+        )
+        .unwrap();
+        let t = port.working_termios();
+        assert_eq!(t.output_baud(), Some(9600));
+        assert_eq!(t.char_size(), 8);
+        assert!(t.is_set(FlagGroup::Control, libc::PARENB | libc::PARODD));
+        assert!(!t.is_set(FlagGroup::Control, libc::CSTOPB));
+        assert!(!t.is_set(FlagGroup::Control, libc::CRTSCTS));
+        assert!(t.is_set(FlagGroup::Input, libc::IXON | libc::IXOFF));
+
+        port.configure_serial(&SerialConfig::new(9600)).unwrap();
+        let t = port.working_termios();
+        assert!(!t.is_set(FlagGroup::Control, libc::PARENB));
+        assert!(!t.is_set(FlagGroup::Input, libc::INPCK));
+        assert!(!t.is_set(FlagGroup::Input, libc::IXON));
+    }
+
+    #[test]
+    fn invalid_config() {
+        let (_master, slave) = openpty().unwrap();
+        let mut port = Term::new((), slave).unwrap();
+        let before = port.working_termios();
+        assert!(port.configure_serial(&SerialConfig::new(12345)).is_err());
+        assert!(port
+            .configure_serial(SerialConfig::new(9600).data_bits(9))
+            .is_err());
+        assert_eq!(port.working_termios(), before);
+    }
+
+    #[test]
+    fn exclusive_open() {
+        // CAP_SYS_ADMIN bypasses TIOCEXCL; capabilities are per thread,
+        // so this only affects the thread running this test
+        crate::caps::retain(crate::CapSet::empty()).unwrap();
+        let (_master, slave) = openpty().unwrap();
+        let path = pty_path(&slave);
+        let port = Term::open_serial(&path).unwrap();
+        let e = Term::open_serial(&path).unwrap_err();
+        assert_eq!(e.raw_os_error(), Some(libc::EBUSY));
+        port.set_exclusive(false).unwrap();
+        Term::open_serial(&path).unwrap();
+    }
+
+    #[test]
+    fn drain_and_discard() {
+        let (mut master, slave) = openpty().unwrap();
+        let mut port = Term::open_serial(pty_path(&slave)).unwrap();
+        port.configure_serial(&SerialConfig::default())
+            .unwrap()
+            .set(SetAction::TCSANOW)
+            .unwrap();
+
+        port.write_all(b"hello").unwrap();
+        port.drain().unwrap();
+        assert!(readable(&master, 1000));
+        let mut buf = [0u8; 16];
+        let n = master.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+
+        master.write_all(b"stale").unwrap();
+        assert!(readable(port.input(), 1000));
+        port.discard_buffers().unwrap();
+        assert!(!readable(port.input(), 100));
+        master.write_all(b"fresh").unwrap();
+        assert!(readable(port.input(), 1000));
+        let n = port.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"fresh");
+    }
+}
@@ -0,0 +1,722 @@
+//! Inspection, comparison and `stty`-style formatting of terminal settings.
+use super::invalid;
+use libc::{speed_t, tcflag_t, termios};
+use std::{fmt, io};
+
+/// The four termios flag fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FlagGroup {
+    /// `c_iflag`
+    Input,
+    /// `c_oflag`
+    Output,
+    /// `c_cflag`
+    Control,
+    /// `c_lflag`
+    Local,
+}
+impl FlagGroup {
+    pub const ALL: [FlagGroup; 4] = [Self::Control, Self::Input, Self::Output, Self::Local];
+
+    /// Names and values of the single-bit flags in this group, in the
+    /// order `stty -a` prints them.
+    pub fn flags(&self) -> &'static [(&'static str, tcflag_t)] {
+        match self {
+            Self::Input => INPUT_FLAGS,
+            Self::Output => OUTPUT_FLAGS,
+            Self::Control => CONTROL_FLAGS,
+            Self::Local => LOCAL_FLAGS,
+        }
+    }
+    fn field(&self, t: &termios) -> tcflag_t {
+        match self {
+            Self::Input => t.c_iflag,
+            Self::Output => t.c_oflag,
+            Self::Control => t.c_cflag,
+            Self::Local => t.c_lflag,
+        }
+    }
+    fn field_mut<'a>(&self, t: &'a mut termios) -> &'a mut tcflag_t {
+        match self {
+            Self::Input => &mut t.c_iflag,
+            Self::Output => &mut t.c_oflag,
+            Self::Control => &mut t.c_cflag,
+            Self::Local => &mut t.c_lflag,
+        }
+    }
+}
+
+// not exported by the libc crate on Linux
+const IUCLC: tcflag_t = 0o1000;
+const XCASE: tcflag_t = 0o4;
+
+const INPUT_FLAGS: &[(&str, tcflag_t)] = &[
+    ("ignbrk", libc::IGNBRK),
+    ("brkint", libc::BRKINT),
+    ("ignpar", libc::IGNPAR),
+    ("parmrk", libc::PARMRK),
+    ("inpck", libc::INPCK),
+    ("istrip", libc::ISTRIP),
+    ("inlcr", libc::INLCR),
+    ("igncr", libc::IGNCR),
+    ("icrnl", libc::ICRNL),
+    ("ixon", libc::IXON),
+    ("ixoff", libc::IXOFF),
+    ("iuclc", IUCLC),
+    ("ixany", libc::IXANY),
+    ("imaxbel", libc::IMAXBEL),
+    ("iutf8", libc::IUTF8),
+];
+const OUTPUT_FLAGS: &[(&str, tcflag_t)] = &[
+    ("opost", libc::OPOST),
+    ("olcuc", libc::OLCUC),
+    ("ocrnl", libc::OCRNL),
+    ("onlcr", libc::ONLCR),
+    ("onocr", libc::ONOCR),
+    ("onlret", libc::ONLRET),
+    ("ofill", libc::OFILL),
+    ("ofdel", libc::OFDEL),
+];
+const CONTROL_FLAGS: &[(&str, tcflag_t)] = &[
+    ("parenb", libc::PARENB),
+    ("parodd", libc::PARODD),
+    ("cmspar", libc::CMSPAR),
+    ("hupcl", libc::HUPCL),
+    ("cstopb", libc::CSTOPB),
+    ("cread", libc::CREAD),
+    ("clocal", libc::CLOCAL),
+    ("crtscts", libc::CRTSCTS),
+];
+const LOCAL_FLAGS: &[(&str, tcflag_t)] = &[
+    ("isig", libc::ISIG),
+    ("icanon", libc::ICANON),
+    ("iexten", libc::IEXTEN),
+    ("echo", libc::ECHO),
+    ("echoe", libc::ECHOE),
+    ("echok", libc::ECHOK),
+    ("echonl", libc::ECHONL),
+    ("noflsh", libc::NOFLSH),
+    ("xcase", XCASE),
+    ("tostop", libc::TOSTOP),
+    ("echoprt", libc::ECHOPRT),
+    ("echoctl", libc::ECHOCTL),
+    ("echoke", libc::ECHOKE),
+    ("flusho", libc::FLUSHO),
+    ("extproc", libc::EXTPROC),
+];
+
+/// Control characters (`c_cc` indexes), in the order `stty -a` prints them.
+pub const CONTROL_CHARS: &[(&str, usize)] = &[
+    ("intr", libc::VINTR),
+    ("quit", libc::VQUIT),
+    ("erase", libc::VERASE),
+    ("kill", libc::VKILL),
+    ("eof", libc::VEOF),
+    ("eol", libc::VEOL),
+    ("eol2", libc::VEOL2),
+    ("swtch", libc::VSWTC),
+    ("start", libc::VSTART),
+    ("stop", libc::VSTOP),
+    ("susp", libc::VSUSP),
+    ("rprnt", libc::VREPRINT),
+    ("werase", libc::VWERASE),
+    ("lnext", libc::VLNEXT),
+    ("discard", libc::VDISCARD),
+    ("min", libc::VMIN),
+    ("time", libc::VTIME),
+];
+
+/// Baud rate constants and the rates they stand for.
+pub(crate) const SPEEDS: &[(speed_t, u32)] = &[
+    (libc::B0, 0),
+    (libc::B50, 50),
+    (libc::B75, 75),
+    (libc::B110, 110),
+    (libc::B134, 134),
+    (libc::B150, 150),
+    (libc::B200, 200),
+    (libc::B300, 300),
+    (libc::B600, 600),
+    (libc::B1200, 1200),
+    (libc::B1800, 1800),
+    (libc::B2400, 2400),
+    (libc::B4800, 4800),
+    (libc::B9600, 9600),
+    (libc::B19200, 19200),
+    (libc::B38400, 38400),
+    (libc::B57600, 57600),
+    (libc::B115200, 115200),
+    (libc::B230400, 230400),
+    (libc::B460800, 460800),
+    (libc::B500000, 500000),
+    (libc::B576000, 576000),
+    (libc::B921600, 921600),
+    (libc::B1000000, 1000000),
+    (libc::B1152000, 1152000),
+    (libc::B1500000, 1500000),
+    (libc::B2000000, 2000000),
+    (libc::B2500000, 2500000),
+    (libc::B3000000, 3000000),
+    (libc::B3500000, 3500000),
+    (libc::B4000000, 4000000),
+];
+
+/// Converts a `speed_t` constant to a baud rate.
+pub(crate) fn speed_to_baud(speed: speed_t) -> Option<u32> {
+    SPEEDS.iter().find(|(s, _)| *s == speed).map(|(_, b)| *b)
+}
+
+/// Converts a baud rate to a `speed_t` constant.
+pub(crate) fn baud_to_speed(baud: u32) -> Option<speed_t> {
+    SPEEDS.iter().find(|(_, b)| *b == baud).map(|(s, _)| *s)
+}
+
+/// Number of `c_cc` entries written by `stty -g` on Linux.
+const STTY_NCCS: usize = 32;
+
+/// A snapshot of terminal settings, as returned by
+/// [`crate::Term::original_termios()`] and friends.
+///
+/// The `Display` implementation prints the settings like `stty -a` does,
+/// and the `Debug` implementation lists only the flags that are set. See
+/// also [`Self::to_stty_g()`] and [`Self::diff()`].
+///
+/// Example, logging what a program did to the terminal:
+///
+/// ```
+/// use std::process::Command;
+/// let t = Term::new((), 1)?;
+/// let before = t.current_termios()?;
+/// Command::new("some-program").status()?;
+/// let after = t.current_termios()?;
+/// eprintln!("changed: {}", before.diff(&after));
+/// eprintln!("restore with: stty {}", before.to_stty_g());
+/// ```
+#[derive(Clone, Copy)]
+pub struct Termios(termios);
+impl Termios {
+    /// Returns the underlying [`libc::termios`].
+    pub fn as_raw(&self) -> &termios {
+        &self.0
+    }
+    /// Returns true if `flag` is set in the given group.
+    pub fn is_set(&self, group: FlagGroup, flag: tcflag_t) -> bool {
+        group.field(&self.0) & flag == flag
+    }
+    /// Looks up a flag by its `stty` name, returning its group and value.
+    pub fn flag_by_name(name: &str) -> Option<(FlagGroup, tcflag_t)> {
+        FlagGroup::ALL.iter().find_map(|g| {
+            g.flags()
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, f)| (*g, *f))
+        })
+    }
+    /// Returns the value of a control character, see [`CONTROL_CHARS`].
+    pub fn control_char(&self, index: usize) -> u8 {
+        self.0.c_cc[index]
+    }
+    /// Number of data bits per character (`cs5` through `cs8`).
+    pub fn char_size(&self) -> u8 {
+        match self.0.c_cflag & libc::CSIZE {
+            libc::CS5 => 5,
+            libc::CS6 => 6,
+            libc::CS7 => 7,
+            _ => 8,
+        }
+    }
+    /// Input baud rate, if it is a standard rate.
+    pub fn input_baud(&self) -> Option<u32> {
+        speed_to_baud(unsafe { libc::cfgetispeed(&self.0) })
+    }
+    /// Output baud rate, if it is a standard rate.
+    pub fn output_baud(&self) -> Option<u32> {
+        speed_to_baud(unsafe { libc::cfgetospeed(&self.0) })
+    }
+    /// Names of the flags that are set, prefixed with `-` for those that
+    /// are not, in `stty -a` order.
+    fn flag_words(&self, group: FlagGroup) -> impl Iterator<Item = String> + '_ {
+        group.flags().iter().map(move |(name, f)| {
+            if self.is_set(group, *f) {
+                name.to_string()
+            } else {
+                format!("-{name}")
+            }
+        })
+    }
+    /// Formats the settings like `stty -g` does: the four flag fields and
+    /// the control characters as colon separated hex numbers. The output
+    /// can be passed to `stty` to restore the settings.
+    pub fn to_stty_g(&self) -> String {
+        let t = &self.0;
+        let mut s = format!(
+            "{:x}:{:x}:{:x}:{:x}",
+            t.c_iflag, t.c_oflag, t.c_cflag, t.c_lflag
+        );
+        for c in t.c_cc.iter().take(STTY_NCCS) {
+            s.push_str(&format!(":{c:x}"));
+        }
+        s
+    }
+    /// Parses the output of `stty -g`, or of [`Self::to_stty_g()`].
+    pub fn from_stty_g(s: &str) -> io::Result<Self> {
+        let mut parts = s.trim().split(':');
+        let mut next_hex = |what: &str| -> io::Result<tcflag_t> {
+            let part = parts
+                .next()
+                .ok_or_else(|| invalid(format!("missing {what}")))?;
+            tcflag_t::from_str_radix(part, 16)
+                .map_err(|_| invalid(format!("{what} {part:?} is not a hex number")))
+        };
+        let mut t: termios = unsafe { std::mem::zeroed() };
+        t.c_iflag = next_hex("iflag")?;
+        t.c_oflag = next_hex("oflag")?;
+        t.c_cflag = next_hex("cflag")?;
+        t.c_lflag = next_hex("lflag")?;
+        for i in 0..STTY_NCCS.min(t.c_cc.len()) {
+            let c = next_hex("control character")?;
+            t.c_cc[i] = u8::try_from(c)
+                .map_err(|_| invalid(format!("control character {c:x} is too large")))?;
+        }
+        if parts.next().is_some() {
+            return Err(invalid("too many fields".into()));
+        }
+        // the speed is kept in the cflag bits, copy it to the speed fields
+        let speed = t.c_cflag & libc::CBAUD;
+        unsafe {
+            libc::cfsetispeed(&mut t, speed);
+            libc::cfsetospeed(&mut t, speed);
+        }
+        Ok(Self(t))
+    }
+    /// Applies `stty` style settings, such as `"-echo icanon intr ^C min 1"`.
+    /// Flag names are negated with a leading `-`. Control characters take
+    /// `^X`, `^?`, `undef` or a literal character, except for `min` and
+    /// `time` which take numbers. `cs5` to `cs8` set the character size,
+    /// and a bare number, `ispeed N` or `ospeed N` set the baud rate; Linux
+    /// keeps a single rate for both directions, so all three set both.
+    /// Combination settings like `raw` and `sane` are not supported.
+    pub fn apply_stty(&mut self, settings: &str) -> io::Result<()> {
+        let mut words = settings.split_whitespace();
+        while let Some(word) = words.next() {
+            let (negate, name) = match word.strip_prefix('-') {
+                Some(name) => (true, name),
+                None => (false, word),
+            };
+            if let Some((group, flag)) = Self::flag_by_name(name) {
+                let field = group.field_mut(&mut self.0);
+                if negate {
+                    *field &= !flag;
+                } else {
+                    *field |= flag;
+                }
+                continue;
+            }
+            if negate {
+                return Err(invalid(format!("unknown flag {word:?}")));
+            }
+            if let Some(bits) = name.strip_prefix("cs").and_then(|b| b.parse::<u8>().ok()) {
+                let size = match bits {
+                    5 => libc::CS5,
+                    6 => libc::CS6,
+                    7 => libc::CS7,
+                    8 => libc::CS8,
+                    _ => return Err(invalid(format!("invalid character size {word:?}"))),
+                };
+                self.0.c_cflag = (self.0.c_cflag & !libc::CSIZE) | size;
+                continue;
+            }
+            if let Ok(baud) = name.parse::<u32>() {
+                self.set_baud(baud, true, true)?;
+                continue;
+            }
+            let mut value = || {
+                words
+                    .next()
+                    .ok_or_else(|| invalid(format!("missing argument to {word:?}")))
+            };
+            match name {
+                "ispeed" | "ospeed" => {
+                    let v = value()?;
+                    let baud = v
+                        .parse::<u32>()
+                        .map_err(|_| invalid(format!("invalid speed {v:?}")))?;
+                    self.set_baud(baud, name == "ispeed", name == "ospeed")?;
+                }
+                _ => {
+                    let (_, index) = CONTROL_CHARS
+                        .iter()
+                        .find(|(n, _)| *n == name)
+                        .ok_or_else(|| invalid(format!("unknown setting {word:?}")))?;
+                    let v = value()?;
+                    self.0.c_cc[*index] = if *index == libc::VMIN || *index == libc::VTIME {
+                        v.parse::<u8>()
+                            .map_err(|_| invalid(format!("invalid number {v:?} for {name}")))?
+                    } else {
+                        parse_control_char(v)?
+                    };
+                }
+            }
+        }
+        Ok(())
+    }
+    fn set_baud(&mut self, baud: u32, input: bool, output: bool) -> io::Result<()> {
+        let speed =
+            baud_to_speed(baud).ok_or_else(|| invalid(format!("unsupported baud rate {baud}")))?;
+        if input {
+            unsafe { libc::cfsetispeed(&mut self.0, speed) };
+        }
+        if output {
+            unsafe { libc::cfsetospeed(&mut self.0, speed) };
+        }
+        Ok(())
+    }
+    /// Lists the differences between `self` and `other`, from the point of
+    /// view of changing `self` into `other`.
+    pub fn diff(&self, other: &Termios) -> TermiosDiff {
+        let mut changes = Vec::new();
+        if self.input_baud() != other.input_baud() || self.output_baud() != other.output_baud() {
+            changes.push(Change::Speed {
+                from: self.output_baud(),
+                to: other.output_baud(),
+            });
+        }
+        if self.char_size() != other.char_size() {
+            changes.push(Change::CharSize {
+                from: self.char_size(),
+                to: other.char_size(),
+            });
+        }
+        for group in FlagGroup::ALL {
+            for (name, flag) in group.flags() {
+                match (self.is_set(group, *flag), other.is_set(group, *flag)) {
+                    (false, true) => changes.push(Change::Set { group, name }),
+                    (true, false) => changes.push(Change::Cleared { group, name }),
+                    _ => (),
+                }
+            }
+        }
+        for (name, index) in CONTROL_CHARS {
+            let (from, to) = (self.0.c_cc[*index], other.0.c_cc[*index]);
+            if from != to {
+                changes.push(Change::ControlChar { name, from, to });
+            }
+        }
+        TermiosDiff(changes)
+    }
+}
+impl From<termios> for Termios {
+    fn from(t: termios) -> Self {
+        Self(t)
+    }
+}
+impl From<Termios> for termios {
+    fn from(t: Termios) -> Self {
+        t.0
+    }
+}
+impl PartialEq for Termios {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (&self.0, &other.0);
+        a.c_iflag == b.c_iflag
+            && a.c_oflag == b.c_oflag
+            && a.c_cflag == b.c_cflag
+            && a.c_lflag == b.c_lflag
+            && a.c_line == b.c_line
+            && a.c_cc == b.c_cc
+            && self.input_baud() == other.input_baud()
+            && self.output_baud() == other.output_baud()
+    }
+}
+impl Eq for Termios {}
+impl fmt::Debug for Termios {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Termios");
+        for group in FlagGroup::ALL {
+            let set: Vec<&str> = group
+                .flags()
+                .iter()
+                .filter(|(_, flag)| self.is_set(group, *flag))
+                .map(|(name, _)| *name)
+                .collect();
+            d.field(&format!("{group:?}").to_lowercase(), &set);
+        }
+        d.field("char_size", &self.char_size())
+            .field("baud", &self.output_baud())
+            .finish()
+    }
+}
+/// Prints the settings in the style of `stty -a`.
+impl fmt::Display for Termios {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.output_baud() {
+            Some(baud) => write!(f, "speed {baud} baud;")?,
+            None => write!(f, "speed unknown;")?,
+        }
+        writeln!(f, " line = {};", self.0.c_line)?;
+        let mut sep = "";
+        for (name, index) in CONTROL_CHARS {
+            let c = self.0.c_cc[*index];
+            if *index == libc::VMIN || *index == libc::VTIME {
+                write!(f, "{sep}{name} = {c};")?;
+            } else {
+                write!(f, "{sep}{name} = {};", ControlChar(c))?;
+            }
+            sep = " ";
+        }
+        writeln!(f)?;
+        for group in FlagGroup::ALL {
+            let mut words: Vec<String> = self.flag_words(group).collect();
+            if group == FlagGroup::Control {
+                words.insert(3, format!("cs{}", self.char_size()));
+            }
+            writeln!(f, "{}", words.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A single difference between two [`Termios`] snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Set {
+        group: FlagGroup,
+        name: &'static str,
+    },
+    Cleared {
+        group: FlagGroup,
+        name: &'static str,
+    },
+    ControlChar {
+        name: &'static str,
+        from: u8,
+        to: u8,
+    },
+    CharSize {
+        from: u8,
+        to: u8,
+    },
+    Speed {
+        from: Option<u32>,
+        to: Option<u32>,
+    },
+}
+/// Prints the change as `stty` would take it, for example `-echo` or
+/// `intr ^C->^X`.
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Set { name, .. } => write!(f, "{name}"),
+            Self::Cleared { name, .. } => write!(f, "-{name}"),
+            Self::ControlChar { name, from, to } if *name == "min" || *name == "time" => {
+                write!(f, "{name} {from}->{to}")
+            }
+            Self::ControlChar { name, from, to } => {
+                write!(f, "{name} {}->{}", ControlChar(*from), ControlChar(*to))
+            }
+            Self::CharSize { from, to } => write!(f, "cs{from}->cs{to}"),
+            Self::Speed { from, to } => {
+                let baud = |b: &Option<u32>| b.map_or("unknown".to_string(), |b| b.to_string());
+                write!(f, "speed {}->{}", baud(from), baud(to))
+            }
+        }
+    }
+}
+
+/// The result of [`Termios::diff()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermiosDiff(Vec<Change>);
+impl TermiosDiff {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn changes(&self) -> &[Change] {
+        &self.0
+    }
+}
+/// Prints the changes separated by spaces, or `(none)`.
+impl fmt::Display for TermiosDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "(none)");
+        }
+        let mut sep = "";
+        for change in self.0.iter() {
+            write!(f, "{sep}{change}")?;
+            sep = " ";
+        }
+        Ok(())
+    }
+}
+
+/// Formats a control character the way `stty` does.
+struct ControlChar(u8);
+impl fmt::Display for ControlChar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => write!(f, "<undef>"),
+            127 => write!(f, "^?"),
+            c @ 1..=31 => write!(f, "^{}", (c + 64) as char),
+            c @ 128..=159 => write!(f, "M-^{}", (c - 64) as char),
+            255 => write!(f, "M-^?"),
+            c @ 160..=254 => write!(f, "M-{}", (c - 128) as char),
+            c => write!(f, "{}", c as char),
+        }
+    }
+}
+
+fn parse_control_char(s: &str) -> io::Result<u8> {
+    let bytes = s.as_bytes();
+    match bytes {
+        b"undef" | b"^-" | b"<undef>" => Ok(0),
+        b"^?" => Ok(127),
+        [b'^', c] if c.is_ascii_alphabetic() || (b'@'..=b'_').contains(c) => {
+            Ok(c.to_ascii_uppercase() - 64)
+        }
+        [c] => Ok(*c),
+        _ => Err(invalid(format!("invalid control character {s:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `stty -g` of a typical Linux terminal at 38400 baud
+    const SANE: &str =
+        "500:5:bf:8a3b:3:1c:7f:15:4:0:1:0:11:13:1a:0:12:f:17:16:0:0:0:0:0:0:0:0:0:0:0:0:0:0:0:0";
+
+    fn sane() -> Termios {
+        Termios::from_stty_g(SANE).unwrap()
+    }
+
+    #[test]
+    fn stty_g_round_trip() {
+        let t = sane();
+        assert!(t.is_set(FlagGroup::Input, libc::ICRNL | libc::IXON));
+        assert!(t.is_set(FlagGroup::Local, libc::ECHO));
+        assert!(!t.is_set(FlagGroup::Control, libc::PARENB));
+        assert_eq!(t.control_char(libc::VINTR), 3);
+        assert_eq!(t.control_char(libc::VMIN), 1);
+        assert_eq!(t.char_size(), 8);
+        assert_eq!(t.input_baud(), Some(38400));
+        assert_eq!(t.output_baud(), Some(38400));
+        assert_eq!(t.to_stty_g(), SANE);
+        assert_eq!(Termios::from_stty_g(&format!("{SANE}\n")).unwrap(), t);
+    }
+
+    #[test]
+    fn stty_g_errors() {
+        assert!(Termios::from_stty_g("").is_err());
+        assert!(Termios::from_stty_g("500:5:bf").is_err());
+        assert!(Termios::from_stty_g(&SANE.replacen("500", "zz", 1)).is_err());
+        assert!(Termios::from_stty_g(&format!("{SANE}:0")).is_err());
+        assert!(Termios::from_stty_g(&SANE.replacen(":3:", ":100:", 1)).is_err());
+    }
+
+    #[test]
+    fn apply_stty() {
+        let mut t = sane();
+        t.apply_stty("-echo -icrnl parenb intr ^x erase ^? kill undef min 5 time 2 cs7 9600")
+            .unwrap();
+        assert!(!t.is_set(FlagGroup::Local, libc::ECHO));
+        assert!(!t.is_set(FlagGroup::Input, libc::ICRNL));
+        assert!(t.is_set(FlagGroup::Control, libc::PARENB));
+        assert_eq!(t.control_char(libc::VINTR), 0x18);
+        assert_eq!(t.control_char(libc::VERASE), 127);
+        assert_eq!(t.control_char(libc::VKILL), 0);
+        assert_eq!(t.control_char(libc::VMIN), 5);
+        assert_eq!(t.control_char(libc::VTIME), 2);
+        assert_eq!(t.char_size(), 7);
+        assert_eq!(t.output_baud(), Some(9600));
+        t.apply_stty("ispeed 1200").unwrap();
+        assert_eq!(t.input_baud(), Some(1200));
+        assert_eq!(t.output_baud(), Some(1200));
+
+        for bad in [
+            "-bogus",
+            "bogus",
+            "cs9",
+            "intr",
+            "min x",
+            "12345",
+            "intr ^^^",
+            "ospeed fast",
+        ] {
+            assert!(sane().apply_stty(bad).is_err(), "{bad:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let before = sane();
+        assert!(before.diff(&before).is_empty());
+        assert_eq!(before.diff(&before).to_string(), "(none)");
+        let mut after = before;
+        after
+            .apply_stty("-echo igncr intr ^X min 0 cs7 115200")
+            .unwrap();
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff.changes(),
+            [
+                Change::Speed {
+                    from: Some(38400),
+                    to: Some(115200)
+                },
+                Change::CharSize { from: 8, to: 7 },
+                Change::Set {
+                    group: FlagGroup::Input,
+                    name: "igncr"
+                },
+                Change::Cleared {
+                    group: FlagGroup::Local,
+                    name: "echo"
+                },
+                Change::ControlChar {
+                    name: "intr",
+                    from: 3,
+                    to: 0x18
+                },
+                Change::ControlChar {
+                    name: "min",
+                    from: 1,
+                    to: 0
+                },
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "speed 38400->115200 cs8->cs7 igncr -echo intr ^C->^X min 1->0"
+        );
+    }
+
+    #[test]
+    fn display_like_stty_a() {
+        let text = sane().to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "speed 38400 baud; line = 0;");
+        assert!(lines[1].starts_with(r"intr = ^C; quit = ^\; erase = ^?; kill = ^U; eof = ^D;"));
+        assert!(lines[1].ends_with("min = 1; time = 0;"));
+        assert_eq!(
+            lines[2],
+            "-parenb -parodd -cmspar cs8 -hupcl -cstopb cread -clocal -crtscts"
+        );
+        assert!(lines[3].starts_with(
+            "-ignbrk -brkint -ignpar -parmrk -inpck -istrip -inlcr -igncr icrnl ixon"
+        ));
+    }
+
+    #[test]
+    fn control_char_names() {
+        assert_eq!(ControlChar(0).to_string(), "<undef>");
+        assert_eq!(ControlChar(3).to_string(), "^C");
+        assert_eq!(ControlChar(127).to_string(), "^?");
+        assert_eq!(ControlChar(b'a').to_string(), "a");
+        assert_eq!(ControlChar(0x83).to_string(), "M-^C");
+        assert_eq!(parse_control_char("^c").unwrap(), 3);
+        assert_eq!(parse_control_char("^-").unwrap(), 0);
+        assert_eq!(parse_control_char("x").unwrap(), b'x');
+        assert!(parse_control_char("xy").is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! Async source of terminal input events. Enabled by the `async` cargo
+//! feature.
+use super::{keys::*, Term};
+use crate::{epoll::SignalPipe, reactor};
+use std::{
+    future::Future,
+    io::{self, Read},
+    os::fd::{AsRawFd, RawFd},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A stream of [`TermEvent`]s read from a [`Term`]'s input, with window
+/// size changes reported as [`TermEvent::Resize`].
+///
+/// This does not change terminal modes; set raw mode on the `Term` before
+/// handing it over, and get it back with [`Self::into_term()`] to reset
+/// it. The input is never switched to nonblocking mode, so it is safe to
+/// use with a stdin that is shared with other processes.
+///
+/// Only one `EventStream` may exist at a time, since it takes over the
+/// `SIGWINCH` handler.
+///
+/// Example:
+///
+/// ```
+/// use std::io::{stdin, stdout};
+/// let mut t = Term::new(stdin(), stdout())?;
+/// t.raw_mode().set(SetAction::TCSAFLUSH)?;
+/// let mut events = EventStream::new(t)?;
+/// block_on(async {
+///     while let Some(ev) = events.next_event().await {
+///         match ev? {
+///             TermEvent::Key(Key::Ctrl('c')) => break,
+///             ev => print!("{ev:?}\r\n"),
+///         }
+///     }
+///     Ok::<_, std::io::Error>(())
+/// })?;
+/// events.into_term().reset(SetAction::TCSANOW)?;
+/// ```
+pub struct EventStream<I: AsRawFd, O> {
+    // dropped first, so the reactor forgets the fds before they are closed
+    interest: [Interest; 2],
+    term: Term<I, O>,
+    decoder: KeyDecoder,
+    winch: SignalPipe,
+    eof: bool,
+}
+impl<I: Read + AsRawFd, O: AsRawFd> EventStream<I, O> {
+    pub fn new(term: Term<I, O>) -> io::Result<Self> {
+        let winch = SignalPipe::new(&[libc::SIGWINCH])?;
+        Ok(Self {
+            interest: [
+                Interest(term.fd_in.as_raw_fd()),
+                Interest(winch.as_raw_fd()),
+            ],
+            term,
+            decoder: KeyDecoder::new(),
+            winch,
+            eof: false,
+        })
+    }
+    /// Gives back the [`Term`], so that it can be reset.
+    pub fn into_term(self) -> Term<I, O> {
+        self.term
+    }
+    /// Polls for the next event. Returns `Ready(None)` once the input
+    /// reaches end of file.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<TermEvent>>> {
+        loop {
+            if let Some(ev) = self.decoder.next_event() {
+                return Poll::Ready(Some(Ok(ev)));
+            }
+            match reactor::poll_readable(self.winch.as_raw_fd(), cx) {
+                Poll::Ready(Ok(())) => {
+                    if let Err(e) = self.winch.drain() {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    return Poll::Ready(Some(
+                        self.term
+                            .size()
+                            .map(|(cols, rows)| TermEvent::Resize { cols, rows }),
+                    ));
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => (),
+            }
+            if self.eof {
+                return Poll::Ready(None);
+            }
+            match reactor::poll_readable(self.term.fd_in.as_raw_fd(), cx) {
+                Poll::Ready(Ok(())) => {
+                    let mut buf = [0u8; 256];
+                    match self.term.read(&mut buf) {
+                        Ok(0) => self.eof = true,
+                        Ok(n) => self.decoder.feed(&buf[..n]),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                        Err(e) => return Poll::Ready(Some(Err(e))),
+                    }
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+    /// Waits for the next event.
+    pub fn next_event(&mut self) -> NextEvent<'_, I, O> {
+        NextEvent { stream: self }
+    }
+}
+
+/// Removes an fd from the reactor when dropped.
+struct Interest(RawFd);
+impl Drop for Interest {
+    fn drop(&mut self) {
+        reactor::forget(self.0);
+    }
+}
+
+/// Future returned by [`EventStream::next_event()`].
+pub struct NextEvent<'a, I: AsRawFd, O> {
+    stream: &'a mut EventStream<I, O>,
+}
+impl<I: Read + AsRawFd, O: AsRawFd> Future for NextEvent<'_, I, O> {
+    type Output = Option<io::Result<TermEvent>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.stream.poll_next(cx)
+    }
+}
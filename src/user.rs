@@ -0,0 +1,159 @@
+//! The user who invoked the program through an escalator, resolved from
+//! the passwd and group databases.
+use nix::unistd::{self, Gid, Uid, User};
+use std::{
+    ffi::CString,
+    fmt, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+/// The account behind a uid: the passwd entry and the supplementary
+/// groups, looked up once. Returned by [`crate::ensure_root()`]. Example:
+///
+/// ```
+/// let user = ensure_root()?;
+/// let config = user.home_path().join(".config/mytool");
+/// if !user.owns(&config)? {
+///     eprintln!("{} is not owned by {user}", config.display());
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvokingUser {
+    name: String,
+    uid: u32,
+    gid: u32,
+    groups: Vec<u32>,
+    gecos: String,
+    home: PathBuf,
+    shell: PathBuf,
+}
+impl InvokingUser {
+    /// Looks up the user with `uid`. Fails with an error of kind
+    /// `NotFound` if there is none.
+    pub fn from_uid(uid: u32) -> io::Result<Self> {
+        let record = User::from_uid(Uid::from_raw(uid))?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no user with uid {uid}"))
+        })?;
+        Self::from_record(record)
+    }
+    /// Looks up the user named `name`. Fails with an error of kind
+    /// `NotFound` if there is none.
+    pub fn from_name(name: &str) -> io::Result<Self> {
+        let record = User::from_name(name)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no user named {name}"))
+        })?;
+        Self::from_record(record)
+    }
+    /// Looks up the user with `uid`, and checks that its name is `name`,
+    /// as when both were passed by an escalator. Fails with an error of
+    /// kind `InvalidData` if they disagree.
+    pub fn with_name(name: &str, uid: u32) -> io::Result<Self> {
+        let user = Self::from_uid(uid)?;
+        if user.name != name {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("uid {uid} belongs to {}, not {name}", user.name),
+            ));
+        }
+        Ok(user)
+    }
+    fn from_record(record: User) -> io::Result<Self> {
+        let cname = CString::new(record.name.as_bytes())?;
+        let groups = unistd::getgrouplist(&cname, record.gid)?;
+        Ok(Self {
+            groups: groups.iter().map(|g| g.as_raw()).collect(),
+            gecos: record.gecos.to_string_lossy().into_owned(),
+            name: record.name,
+            uid: record.uid.as_raw(),
+            gid: record.gid.as_raw(),
+            home: record.dir,
+            shell: record.shell,
+        })
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+    /// The primary group.
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+    /// The supplementary groups, including the primary group.
+    pub fn groups(&self) -> &[u32] {
+        &self.groups
+    }
+    /// The comment field of the passwd entry, usually the full name
+    /// followed by comma separated contact details.
+    pub fn gecos(&self) -> &str {
+        &self.gecos
+    }
+    pub fn home_path(&self) -> &Path {
+        &self.home
+    }
+    /// The login shell.
+    pub fn shell(&self) -> &Path {
+        &self.shell
+    }
+    /// Returns true if the user owns `path`. Symlinks are followed.
+    pub fn owns(&self, path: impl AsRef<Path>) -> io::Result<bool> {
+        Ok(path.as_ref().metadata()?.uid() == self.uid)
+    }
+    pub(crate) fn nix_uid(&self) -> Uid {
+        Uid::from_raw(self.uid)
+    }
+    pub(crate) fn nix_gid(&self) -> Gid {
+        Gid::from_raw(self.gid)
+    }
+    pub(crate) fn nix_groups(&self) -> Vec<Gid> {
+        self.groups.iter().map(|g| Gid::from_raw(*g)).collect()
+    }
+}
+/// Shows the user name.
+impl fmt::Display for InvokingUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let root = InvokingUser::from_uid(0).unwrap();
+        assert_eq!(root.name(), "root");
+        assert_eq!(root.gid(), 0);
+        assert!(root.groups().contains(&0));
+        assert_eq!(root.to_string(), "root");
+        assert_eq!(InvokingUser::from_name("root").unwrap(), root);
+        assert_eq!(InvokingUser::with_name("root", 0).unwrap(), root);
+    }
+
+    #[test]
+    fn lookup_errors() {
+        let e = InvokingUser::with_name("nobody", 0).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let e = InvokingUser::from_name("no-such-user-here").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        // the largest uid is never assigned, since it is (uid_t)-1
+        let e = InvokingUser::from_uid(u32::MAX).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn owns() {
+        let root = InvokingUser::from_uid(0).unwrap();
+        let nobody = InvokingUser::from_name("nobody").unwrap();
+        let path = std::env::temp_dir().join(format!("owns-test-{}", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        std::os::unix::fs::chown(&path, Some(nobody.uid()), None).unwrap();
+        assert!(nobody.owns(&path).unwrap());
+        assert!(!root.owns(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert!(root.owns(&path).is_err());
+    }
+}